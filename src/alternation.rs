@@ -0,0 +1,245 @@
+use regex::bytes::{CaptureLocations, Match, Regex};
+
+
+/// Multiple patterns matched as if they were a single leftmost-first alternation, while
+/// each is compiled on its own. Patterns that are valid alone may not be when combined in
+/// a single regex, e.g. with the `x` flag a comment would swallow the closing group.
+pub struct Alternation {
+  patterns: Box<[Regex]>
+}
+
+
+impl Alternation {
+  pub fn new(patterns: Box<[Regex]>) -> Alternation {
+    Alternation { patterns }
+  }
+
+
+  /// The patterns, in priority order.
+  pub fn patterns(&self) -> &[Regex] {
+    &self.patterns
+  }
+
+
+  /// Whether any of the patterns matches the buffer.
+  pub fn is_match(&self, buffer: &[u8]) -> bool {
+    self.patterns.iter().any(|pattern| pattern.is_match(buffer))
+  }
+
+
+  /// The number of capture groups of the pattern with the most, including group 0.
+  pub fn captures_len(&self) -> usize {
+    self.patterns.iter().map(Regex::captures_len).max().unwrap_or(1)
+  }
+
+
+  /// Find the leftmost match starting at or after `start`, with the index of the pattern
+  /// that produced it. When multiple patterns match at the same position, the first one
+  /// has priority.
+  pub fn find_at<'t>(&self, buffer: &'t [u8], start: usize) -> Option<(usize, Match<'t>)> {
+    self.patterns
+        .iter()
+        .enumerate()
+        .filter_map(|(ix, pattern)| pattern.find_at(buffer, start).map(|m| (ix, m)))
+        .min_by_key(|(_, m)| m.start()) // The first minimum is kept.
+  }
+
+
+  /// Iterate the successive non overlapping matches, with the index of the pattern that
+  /// produced each, as `Regex::find_iter` does for a single pattern.
+  pub fn find_iter<'r, 't>(&'r self, buffer: &'t [u8]) -> Matches<'r, 't> {
+    Matches {
+      patterns: &self.patterns,
+      buffer,
+      next: vec![None; self.patterns.len()].into_boxed_slice(),
+      last_end: 0,
+      last_match: None
+    }
+  }
+
+
+  /// Iterate the slices of the buffer between the matches, as `Regex::split` does for a
+  /// single pattern.
+  pub fn split<'r, 't>(&'r self, buffer: &'t [u8]) -> Split<'r, 't> {
+    Split { matches: self.find_iter(buffer), last: 0 }
+  }
+
+
+  /// The capture groups of a match of the pattern at `index`, which must start at `start`.
+  pub fn captures_at(&self, index: usize, buffer: &[u8], start: usize) -> CaptureLocations {
+    let pattern = &self.patterns[index];
+    let mut locations = pattern.capture_locations();
+
+    pattern.captures_read_at(&mut locations, buffer, start);
+
+    locations
+  }
+}
+
+
+/// The successive matches of an alternation, with the index of the pattern of each.
+pub struct Matches<'r, 't> {
+  patterns: &'r [Regex],
+  buffer: &'t [u8],
+  // The next match of each pattern, if any, from a previous search. It remains valid while
+  // it doesn't start before the search position.
+  next: Box<[Option<Option<Match<'t>>>]>,
+  last_end: usize, // Where the search for the next match starts.
+  last_match: Option<usize> // The end of the previous match.
+}
+
+
+impl<'r, 't> Matches<'r, 't> {
+  /// Find the leftmost match starting at or after `start`, searching again only the
+  /// patterns whose previous match starts before it.
+  fn find_at(&mut self, start: usize) -> Option<(usize, Match<'t>)> {
+    let (patterns, buffer) = (self.patterns, self.buffer);
+
+    for (pattern, next) in patterns.iter().zip(self.next.iter_mut()) {
+      if next.is_none_or(|next| next.is_some_and(|m| m.start() < start)) {
+        *next = Some(pattern.find_at(buffer, start));
+      }
+    }
+
+    self.next
+        .iter()
+        .enumerate()
+        .filter_map(|(ix, next)| next.flatten().map(|m| (ix, m)))
+        .min_by_key(|(_, m)| m.start()) // The first minimum is kept.
+  }
+}
+
+
+impl<'r, 't> Iterator for Matches<'r, 't> {
+  type Item = (usize, Match<'t>);
+
+  fn next(&mut self) -> Option<(usize, Match<'t>)> {
+    loop {
+      if self.last_end > self.buffer.len() {
+        return None;
+      }
+
+      let (ix, m) = self.find_at(self.last_end)?;
+
+      if m.start() == m.end() {
+        // Empty matches must still advance, and those right after a match are skipped.
+        self.last_end = m.end() + 1;
+
+        if self.last_match == Some(m.end()) {
+          continue;
+        }
+      } else {
+        self.last_end = m.end();
+      }
+
+      self.last_match = Some(m.end());
+
+      return Some((ix, m));
+    }
+  }
+}
+
+
+/// The slices of the buffer between the matches of an alternation.
+pub struct Split<'r, 't> {
+  matches: Matches<'r, 't>,
+  last: usize // The end of the previous match.
+}
+
+
+impl<'r, 't> Iterator for Split<'r, 't> {
+  type Item = &'t [u8];
+
+  fn next(&mut self) -> Option<&'t [u8]> {
+    let buffer = self.matches.buffer;
+
+    match self.matches.next() {
+      Some((_, m)) => {
+        let slice = &buffer[self.last .. m.start()];
+        self.last = m.end();
+        Some(slice)
+      }
+
+      None if self.last < buffer.len() => {
+        let slice = &buffer[self.last ..];
+        self.last = buffer.len();
+        Some(slice)
+      }
+
+      None => None
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  fn alternation(patterns: &[&str]) -> Alternation {
+    Alternation::new(patterns.iter().map(|p| Regex::new(p).unwrap()).collect())
+  }
+
+
+  fn find_all(alternation: &Alternation, buffer: &[u8]) -> Vec<(usize, usize, usize)> {
+    alternation.find_iter(buffer).map(|(ix, m)| (ix, m.start(), m.end())).collect()
+  }
+
+
+  #[test]
+  fn leftmost_match_wins() {
+    let alternation = alternation(&["foo", "o+", "hello"]);
+
+    assert_eq!(find_all(&alternation, b"hello foo"), [(2, 0, 5), (0, 6, 9)]);
+  }
+
+
+  #[test]
+  fn first_pattern_wins_at_same_position() {
+    let alternation = alternation(&["ab", "abc"]);
+
+    assert_eq!(find_all(&alternation, b"abc abc"), [(0, 0, 2), (0, 4, 6)]);
+  }
+
+
+  #[test]
+  fn patterns_invalid_when_pasted() {
+    let alternation = alternation(&["(?x)hel # comment", "foo"]);
+
+    assert_eq!(find_all(&alternation, b"hello foo"), [(0, 0, 3), (1, 6, 9)]);
+  }
+
+
+  #[test]
+  fn empty_matches_as_regex() {
+    let buffer = b"abaab";
+
+    for pattern in &["", "a*", "b?", "a*|b"] {
+      let regex = Regex::new(pattern).unwrap();
+      let expected: Vec<_> = regex.find_iter(buffer).map(|m| (0, m.start(), m.end())).collect();
+
+      assert_eq!(find_all(&alternation(&[pattern]), buffer), expected, "pattern {:?}", pattern);
+    }
+  }
+
+
+  #[test]
+  fn split_between_matches() {
+    let alternation = alternation(&["b", "c"]);
+    let slices: Vec<&[u8]> = alternation.split(b"abxcd").collect();
+
+    assert_eq!(slices, [&b"a"[..], b"x", b"d"]);
+  }
+
+
+  #[test]
+  fn captures_of_the_matching_pattern() {
+    let alternation = alternation(&["x(y)", "a(b)(c)"]);
+    let (ix, m) = alternation.find_at(b"--abc", 0).unwrap();
+    let captures = alternation.captures_at(ix, b"--abc", m.start());
+
+    assert_eq!((ix, captures.get(2)), (1, Some((4, 5))));
+    assert_eq!(alternation.captures_len(), 3);
+  }
+}
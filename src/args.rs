@@ -1,20 +1,59 @@
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::env;
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::{self, App, Arg, ArgMatches};
+use clap::{self, App, Arg, ArgGroup, ArgMatches};
 use clap::{crate_authors, crate_version, crate_name, crate_description};
 
+use crate::digest;
+use crate::encoding;
+use crate::format::{self, Template};
+use crate::hex;
+use crate::manifest;
+use crate::literal::Literal;
+use crate::mask::Mask;
+use crate::progress;
+use crate::record::RecordFormat;
+
 
 /// The output mode.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub enum Output {
+  #[default]
   FileName,
   Bytes,
-  Offset
+  Offset,
+  Status,
+  Histogram,
+  Format(Template),
+  Replace(Vec<u8>), // The replacement bytes.
+  Binary,
+  Count,
+  Probe(Box<[usize]>), // The offsets to probe, in order.
+  JsonArray,
+  Hexdump(usize), // The number of bytes per row.
+  CountFiles,
+  CString,
+  Strings(usize), // The minimum length of the strings.
+  BytesHex,
+  Extract(PathBuf), // The directory to write the matches to.
+  Top(usize), // The number of files to rank.
+  ContextHex(usize), // The number of context bytes on either side of the match.
+  ValueHistogram,
+  R2Commands
 }
 
-impl Default for Output {
-  fn default() -> Output { Output::FileName }
+
+/// The width of printed offsets, as specified by `--offset-width`.
+#[derive(Debug, Clone, Copy)]
+pub enum OffsetWidth {
+  Fixed(usize), // The number of hex digits.
+  Auto // As many digits as the size of the input takes.
 }
 
 
@@ -23,9 +62,83 @@ impl Default for Output {
 pub struct Options {
   pub inverse: bool,
   pub case_insensitive: bool,
+  pub literal_newline: bool, // Whether `.` excludes the newline byte, as in line based greps.
+  pub wrap_prefix: String, // The regex fragment prepended to each pattern.
+  pub wrap_suffix: String, // The regex fragment appended to each pattern.
+  pub allow_empty_pattern: bool, // Whether patterns that match at every position are allowed.
+  pub dfa_limit: Option<usize>, // The limit of the compiled program and DFA cache, in bytes.
+  pub backrefs: Box<[(usize, usize)]>, // Pairs of match positions that must hold equal bytes.
+  pub not_followed_by: Option<String>, // The pattern that must not match right after a match.
+  pub word_bytes: Option<Box<[bool]>>, // Whether each byte value is a word byte, if defined.
+  pub prefilter: Option<u8>, // The byte that all matches start with, if given.
   pub trim_ending_newline: bool,
+  pub hex_input: bool, // Whether to decode the input from hex text before searching.
+  pub device: bool, // Whether to read the input in chunks, instead of loading it entirely.
+  pub max_match_bytes: usize, // The longest match that is guaranteed to be found in chunks.
+  pub chunk_size: usize, // The size of the chunks read at once.
+  pub max_memory: Option<usize>, // The size above which files are read in chunks.
+  pub stride: usize, // Only every `stride`th chunk is searched.
+  pub timeout: Option<Duration>, // The time limit for searching each file. Implies chunks.
+  pub offset: usize, // The offset where the search starts in each file.
+  pub reverse: bool, // Whether to process the files in reverse order.
+  // The number of matches left to output across all files, decremented as matches are
+  // found. Present only with `--max-matches-total`.
+  pub remaining_matches: Option<Cell<usize>>,
+  pub require_match: bool, // Whether no match in any file is an error.
+  pub all_match: bool, // Whether success requires every searched file to match.
+  pub no_match_exit_zero: bool, // Whether no match is a success, for the exit status.
+  pub fail_fast: bool, // Whether to stop at the first error, instead of moving on.
+  pub no_buffer_reuse: bool, // Whether to allocate a fresh buffer for each file.
+  pub resume_from: Option<PathBuf>, // The checkpoint file to resume from and update.
+  pub only_files_changed: Option<PathBuf>, // The file of modification times of searched files.
+  // The lowercase extensions of the files to search, if restricted with `--type`.
+  pub types: Option<HashSet<String>>,
   pub non_matching: bool, // Whether to print non matching files. Only true when (-L).
   pub print_filename: bool,
+  pub stdin_filename: Option<String>, // The label of stdin in the output, instead of <stdin>.
+  pub absolute_paths: bool, // Whether to label the files with their canonical path.
+  pub show_pattern: bool, // Whether to annotate matches with the index of the pattern.
+  pub with_count: bool, // Whether to follow listed files with their number of matches.
+  pub min_count: Option<usize>, // The number of matches required to list a file.
+  pub count_breakdown: bool, // Whether to count each pattern on its own.
+  pub count_total: bool, // Whether to follow the counts with their total.
+  pub concat: bool, // Whether to print the matched bytes without any separator.
+  pub pad_to: usize, // The width to pad printed matches to, with `pad_byte`.
+  pub pad_byte: u8,
+  pub hex_row_sep: u8, // The byte terminating the lines of the hexdump.
+  pub before_context: usize, // The number of bytes to print before each match.
+  pub after_context: usize, // The number of bytes to print after each match.
+  pub hash: Option<digest::Algorithm>, // When present, matched bytes are printed as their digest.
+  pub merge_adjacent: bool,
+  pub detect_encoding: bool, // Whether to annotate offsets with the guessed encoding.
+  pub utf16_preview: bool, // Whether to annotate offsets with the surrounding UTF-16 text.
+  pub gaps: bool, // Whether to print the ranges between matches, instead of the matches.
+  pub with_length: bool, // Whether to follow offsets with the length of the match.
+  pub offset_from_end: bool, // Whether to print offsets backward from the end of the input.
+  pub offset_preview: usize, // The number of matched bytes to print after each offset, in hex.
+  pub offset_width: Option<OffsetWidth>, // The width to zero pad offsets to.
+  pub offset_separator: Option<String>, // The separator of offsets, instead of a newline each.
+  pub offset_group: Option<usize>, // The capture group whose offset is printed.
+  pub span: bool, // Whether to print only the span from the first to the last match.
+  pub last: bool, // Whether to print only the last match.
+  pub progress: bool, // Whether to report progress. Only true when stderr is a terminal.
+  // The position of the chunk being searched, set when reading in chunks so that progress
+  // is reported through the whole input rather than each chunk.
+  pub progress_window: Cell<Option<progress::Window>>,
+  pub line_buffered: bool,
+  pub report: bool, // Whether to print a summary line to stderr at the end.
+  pub count_bytes_total: bool, // Whether to print the number of bytes read to stderr at the end.
+  pub si: bool, // Whether sizes are printed in powers of 1000, instead of 1024.
+  pub summary_json: bool, // Whether to print the summary as JSON at the end.
+  pub summary_file: Option<PathBuf>, // Where to write the JSON summary, instead of stderr.
+  pub output_by_pattern: Option<PathBuf>, // The directory of the outputs of each pattern.
+  pub pe_section: Option<String>, // The name of the PE section to search, if any.
+  pub pe_rva: bool, // Whether to report offsets in the PE section as RVAs.
+  pub record_format: Option<RecordFormat>,
+  pub mask: Option<Mask>, // When present, the pattern is ignored in favor of the mask.
+  pub literal: Option<Literal>, // When present, the pattern is ignored in favor of the literal.
+  pub within: Option<(String, String)>, // The start and end delimiter patterns.
+  pub relative_to: Option<String>, // The anchor pattern that offsets are relative to.
   pub output: Output
 }
 
@@ -34,7 +147,8 @@ pub struct Options {
 #[derive(Default, Debug)]
 pub struct Args {
   pub options: Options,
-  pub pattern: String,
+  pub patterns: Box<[String]>,
+  pub manifest: Option<Box<[manifest::Entry]>>, // When present, replaces the patterns.
   pub files: Box<[PathBuf]>
 }
 
@@ -44,7 +158,7 @@ pub struct Args {
 pub enum Command {
   Help(String),
   Version(String),
-  Grep(Args)
+  Grep(Box<Args>) // Boxed, as the options are much larger than the messages.
 }
 
 
@@ -59,6 +173,147 @@ pub struct Error {
 /// The path used to denote reading from stdin.
 pub const STDIN: &str = "-";
 
+/// The default for `--max-match-bytes`.
+const DEFAULT_MAX_MATCH_BYTES: usize = 4096;
+
+/// The default for `--chunk-size`.
+const DEFAULT_CHUNK_SIZE: usize = 1 << 20; // 1 MiB
+
+/// The output mode flags, which override each other so that the last one given wins.
+/// An arg overriding itself is allowed by clap, so the list is shared by all of them.
+const OUTPUT_MODES: &[&str] = &[
+  "only-matching",
+  "byte-offset",
+  "files-with-matches",
+  "files-without-matches",
+  "dry-match",
+  "offset-histogram",
+  "format",
+  "replace-file",
+  "binary-output",
+  "count",
+  "probe-offsets",
+  "json-array",
+  "hexdump",
+  "count-files",
+  "cstring",
+  "extract",
+  "strings",
+  "bytes-hex",
+  "top",
+  "context-hex",
+  "group-by-value",
+  "r2-commands",
+];
+
+/// The flags that format or annotate the offsets of `--byte-offset`. Signatures given with
+/// `--mask` or `--literal` only print plain offsets, so they conflict with all of them.
+const OFFSET_FORMATS: &[&str] = &[
+  "gaps",
+  "merge-adjacent",
+  "span",
+  "last",
+  "show-pattern",
+  "with-length",
+  "offset-width",
+  "offset-from-end",
+  "offset-separator",
+  "offset-preview",
+  "detect-encoding",
+  "utf16-preview",
+];
+
+
+
+/// Parse a non negative integer, in decimal or in hex with the `0x` prefix.
+fn parse_number(number: &str) -> Result<usize, String> {
+  let result =
+    if number.starts_with("0x") || number.starts_with("0X") {
+      usize::from_str_radix(&number[2..], 16)
+    } else {
+      number.parse()
+    };
+
+  result.map_err(|_| format!("invalid number '{}'", number))
+}
+
+
+/// Parse a size, as a number optionally followed by one of the binary suffixes K, M or G.
+fn parse_size(size: &str) -> Result<usize, String> {
+  let (number, multiplier) = match size.chars().last() {
+    Some('k') | Some('K') => (&size[.. size.len() - 1], 1 << 10),
+    Some('m') | Some('M') => (&size[.. size.len() - 1], 1 << 20),
+    Some('g') | Some('G') => (&size[.. size.len() - 1], 1 << 30),
+    _ => (size, 1)
+  };
+
+  parse_number(number)?.checked_mul(multiplier)
+                       .ok_or_else(|| format!("size too large '{}'", size))
+}
+
+
+
+/// Parse a byte equality constraint, as two positions separated by `=`, e.g. `0=4`.
+fn parse_backref(backref: &str) -> Result<(usize, usize), String> {
+  let separator = backref.find('=')
+                         .ok_or_else(|| format!("missing '=' in constraint '{}'", backref))?;
+
+  Ok((parse_number(&backref[.. separator])?, parse_number(&backref[separator + 1 ..])?))
+}
+
+
+/// Parse a pattern spec, as inline regex flags and a pattern separated by `:`, e.g. `i:foo`.
+/// Returns the pattern with the flags applied to it alone.
+fn parse_pattern_spec(spec: &str) -> Result<String, String> {
+  let separator = spec.find(':')
+                      .ok_or_else(|| format!("missing ':' in pattern spec '{}'", spec))?;
+
+  let flags = &spec[.. separator];
+
+  if !flags.chars().all(|c| "imsxU-".contains(c)) {
+    return Err(format!("invalid flags '{}' in pattern spec '{}'", flags, spec));
+  }
+
+  // With the `x` flag, a trailing comment would swallow the closing parenthesis, so the
+  // pattern ends with a newline, which is whitespace in that mode.
+  let verbose = flags.split('-').next().unwrap_or("").contains('x');
+
+  Ok(format!("(?{}:{}{})", flags, &spec[separator + 1 ..], if verbose { "\n" } else { "" }))
+}
+
+
+/// Parse a single byte, as two hex digits.
+fn parse_byte(hex: &str) -> Result<u8, String> {
+  match hex::decode(hex).as_ref().map(Vec::as_slice) {
+    Ok([byte]) => Ok(*byte),
+    _ => Err(format!("invalid byte '{}'", hex))
+  }
+}
+
+
+/// Parse a set of bytes, as comma separated hex bytes or inclusive ranges, e.g. `30-39,5f`.
+/// Returns the membership of each byte value.
+fn parse_byte_set(set: &str) -> Result<Box<[bool]>, String> {
+  let mut members = vec![false; 256];
+
+  for item in set.split(',') {
+    let (first, last) = match item.find('-') {
+      Some(ix) => (parse_byte(&item[.. ix])?, parse_byte(&item[ix + 1 ..])?),
+      None => { let b = parse_byte(item)?; (b, b) }
+    };
+
+    if first > last {
+      return Err(format!("invalid byte range '{}'", item));
+    }
+
+    for b in first ..= last {
+      members[b as usize] = true;
+    }
+  }
+
+  Ok(members.into_boxed_slice())
+}
+
 
 
 /// Build clap's `App`. This specifies all arguments and metadata.
@@ -67,11 +322,11 @@ fn build_app() -> App<'static, 'static> {
     .about(crate_description!())
     .author(crate_authors!())
     .version(crate_version!())
-    .template("{bin} {version}\nMade by {author}\n{about}\n\n{usage}\n\nFLAGS:\n{flags}")
+    .template("{bin} {version}\nMade by {author}\n{about}\n\n{usage}\n\nFLAGS:\n{flags}\n\nOPTIONS:\n{options}")
     // Positional arguments:
     .arg(
       Arg::with_name("pattern")
-          .required(true)
+          .required_unless_one(&["regexp", "pattern-spec", "pattern-env", "manifest"])
           .index(1)
     )
     .arg(
@@ -81,6 +336,82 @@ fn build_app() -> App<'static, 'static> {
         .index(2)
     )
     // Matching flags:
+    .arg(
+      Arg::with_name("regexp")
+        .short("e")
+        .long("regexp")
+        .value_name("pattern")
+        .help("Use the given pattern. May be specified multiple times to match any of the \
+               patterns. Each offset is reported once, for the first pattern that matches \
+               there. Makes all positional arguments files")
+        .multiple(true)
+        .number_of_values(1)
+    )
+    .arg(
+      Arg::with_name("pattern-spec")
+        .long("pattern-spec")
+        .value_name("spec")
+        .help("Use the given pattern with its own flags, as in i:foo, where the flags before \
+               the ':' are any of imsxU, and those after a '-' are cleared, as in -i:foo. May \
+               be specified multiple times, and the patterns follow those of -e. Makes all \
+               positional arguments files")
+        .multiple(true)
+        .number_of_values(1)
+        .allow_hyphen_values(true)
+        .validator(|spec| parse_pattern_spec(&spec).map(|_| ()))
+        .conflicts_with_all(&["pattern-env", "glob"])
+    )
+    .arg(
+      Arg::with_name("glob")
+        .long("glob")
+        .help("Interpret the patterns as byte globs: hex bytes, '?' for any single byte and \
+               '*' for any sequence of bytes, as in 4d5a?00*5045")
+        .conflicts_with_all(&["manifest", "mask", "literal"])
+    )
+    .arg(
+      Arg::with_name("utf16le")
+        .long("utf16le")
+        .help("Interpret the patterns as text, and search for it encoded as UTF-16 little \
+               endian, as in Windows strings. Offsets are those of the encoded bytes")
+        .conflicts_with_all(&["utf16be", "glob", "pattern-spec", "manifest", "mask", "literal"])
+    )
+    .arg(
+      Arg::with_name("utf16be")
+        .long("utf16be")
+        .help("Interpret the patterns as text, and search for it encoded as UTF-16 big endian")
+        .conflicts_with_all(&["glob", "pattern-spec", "manifest", "mask", "literal"])
+    )
+    .arg(
+      Arg::with_name("allow-empty-pattern")
+        .long("allow-empty-pattern")
+        .help("Allow an empty pattern, which matches at every position of the input")
+    )
+    .arg(
+      Arg::with_name("pattern-env")
+        .long("pattern-env")
+        .value_name("var")
+        .help("Read the pattern from the given environment variable. Makes all positional \
+               arguments files")
+        .conflicts_with("regexp")
+    )
+    .arg(
+      Arg::with_name("manifest")
+        .long("manifest")
+        .value_name("path")
+        .help("Read the patterns from the given manifest, with one '<glob> => <pattern>' \
+               entry per line. Each file is searched for the patterns of all the entries \
+               whose glob matches it, and skipped if none does. Globs with a '/' match the \
+               whole path, otherwise the file name. Makes all positional arguments files")
+        .conflicts_with_all(&[
+          "regexp",
+          "pattern-spec",
+          "pattern-env",
+          "mask",
+          "literal",
+          "show-pattern",
+          "count-breakdown",
+        ])
+    )
     .arg(
       Arg::with_name("invert-match")
         .short("v")
@@ -93,6 +424,181 @@ fn build_app() -> App<'static, 'static> {
         .long("ignore-case")
         .help("Case insensitive matching for ASCII alphabetic characters")
     )
+    .arg(
+      Arg::with_name("literal-newline")
+        .long("literal-newline")
+        .help("Make '.' not match the newline byte, which then only matches an explicit \\n. \
+               By default, '.' matches any byte")
+    )
+    .arg(
+      Arg::with_name("wrap-prefix")
+        .long("wrap-prefix")
+        .value_name("regex")
+        .help("Prepend the given regex fragment to each pattern, e.g. (?i) or ^")
+        .conflicts_with_all(&["mask", "literal"])
+    )
+    .arg(
+      Arg::with_name("wrap-suffix")
+        .long("wrap-suffix")
+        .value_name("regex")
+        .help("Append the given regex fragment to each pattern, e.g. $")
+        .conflicts_with_all(&["mask", "literal"])
+    )
+    .arg(
+      Arg::with_name("backref")
+        .long("backref")
+        .value_name("a=b")
+        .help("Only select matches whose bytes at positions a and b, relative to the start of \
+               the match, are equal. May be specified multiple times")
+        .validator(|backref| parse_backref(&backref).map(|_| ()))
+        .multiple(true)
+        .number_of_values(1)
+        .conflicts_with_all(&["invert-match", "mask", "literal"])
+    )
+    .arg(
+      Arg::with_name("not-followed-by")
+        .long("not-followed-by")
+        .value_name("pattern")
+        .help("Only select matches that are not immediately followed by a match of the given \
+               pattern, which is matched from the end of the match, as a lookahead")
+        .conflicts_with_all(&["invert-match", "mask", "literal", "device", "timeout", "max-memory"])
+    )
+    .arg(
+      Arg::with_name("dfa-limit")
+        .long("dfa-limit")
+        .value_name("size")
+        .help("Bound the memory of each pattern. The limit applies both to the compiled \
+               program, and to the DFA cache used while searching. Patterns whose compiled \
+               program exceeds it are rejected. When the DFA cache fills up while searching, \
+               matching silently falls back to slower engines, which can be much slower on \
+               large inputs. Accepts the K, M and G suffixes")
+        .validator(|size| parse_size(&size).map(|_| ()))
+    )
+    .arg(
+      Arg::with_name("prefilter")
+        .long("prefilter")
+        .value_name("byte")
+        .help("Only select matches that start with the given hex byte. The byte is located \
+               first, and the pattern is only run from there, which is faster when the byte \
+               is rare")
+        .validator(|byte| parse_byte(&byte).map(|_| ()))
+        .conflicts_with_all(&[
+          "invert-match",
+          "mask",
+          "literal",
+          "files-without-matches",
+          "count-breakdown",
+          "format",
+          "replace-file",
+          "strings",
+          "offset-group",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("word-bytes")
+        .long("word-bytes")
+        .value_name("set")
+        .help("Only select matches that are not preceded nor followed by a word byte. The \
+               word bytes are given as comma separated hex bytes or ranges, e.g. 20-7e")
+        .validator(|set| parse_byte_set(&set).map(|_| ()))
+        .conflicts_with_all(&[
+          "invert-match",
+          "mask",
+          "literal",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("mask")
+        .long("mask")
+        .value_name("hexmask")
+        .help("Interpret the pattern as hex bytes, matching only the bits set in the given \
+               hex mask of the same length. Prints the plain offset of every occurrence")
+        .validator(|mask| hex::decode(mask).map(|_| ()).map_err(|_| "invalid hex".to_owned()))
+        .conflicts_with_all(OFFSET_FORMATS)
+        .conflicts_with_all(&[
+          "regexp",
+          "pattern-spec",
+          "invert-match",
+          "only-matching",
+          "files-with-matches",
+          "files-without-matches",
+          "dry-match",
+          "offset-histogram",
+          "format",
+          "replace-file",
+          "binary-output",
+          "count",
+          "count-files",
+          "cstring",
+          "extract",
+          "strings",
+          "bytes-hex",
+          "top",
+          "group-by-value",
+          "r2-commands",
+          "context-hex",
+        ])
+    )
+    .arg(
+      Arg::with_name("literal")
+        .long("literal")
+        .help("Interpret the pattern as hex bytes, matched literally with a rolling hash \
+               instead of the regex engine, which is faster for long signatures. Prints the \
+               plain offset of every occurrence")
+        .conflicts_with_all(OFFSET_FORMATS)
+        .conflicts_with_all(&[
+          "regexp",
+          "pattern-spec",
+          "invert-match",
+          "mask",
+          "only-matching",
+          "files-with-matches",
+          "files-without-matches",
+          "dry-match",
+          "offset-histogram",
+          "format",
+          "replace-file",
+          "binary-output",
+          "count",
+          "count-files",
+          "cstring",
+          "extract",
+          "strings",
+          "bytes-hex",
+          "top",
+          "group-by-value",
+          "r2-commands",
+          "context-hex",
+        ])
+    )
+    .arg(
+      Arg::with_name("also-reversed")
+        .long("also-reversed")
+        .help("Also match the literal with its bytes reversed, in the same pass, to find it \
+               in either byte order")
+        .requires("literal")
+    )
+    .arg(
+      Arg::with_name("within")
+        .long("within")
+        .value_names(&["start", "end"])
+        .help("Match only within the regions between the start and end delimiter patterns. \
+               An unterminated region extends to the end of the input")
+        .number_of_values(2)
+        .conflicts_with_all(&[
+          "files-with-matches",
+          "files-without-matches",
+          "dry-match",
+          "replace-file",
+          "count",
+        ])
+    )
     // Input flags:
     .arg(
       Arg::with_name("trim-ending-newline")
@@ -100,7 +606,524 @@ fn build_app() -> App<'static, 'static> {
         .long("trim-ending-newline")
         .help("If the file ends with a newline, disconsider the last byte")
     )
+    .arg(
+      Arg::with_name("record-format")
+        .long("record-format")
+        .value_name("format")
+        .help("Split the input into length-prefixed records, and search each one on its own. \
+               Offsets are relative to the record, which is labeled as file[index]")
+        .possible_values(&["netstring", "len32le"])
+    )
+    .arg(
+      Arg::with_name("pe-section")
+        .long("pe-section")
+        .value_name("name")
+        .help("Search only the given section of PE files, e.g. .rdata. Offsets remain file \
+               offsets. Fails on files that are not PE")
+        .conflicts_with_all(&["offset", "record-format", "device", "timeout", "max-memory"])
+    )
+    .arg(
+      Arg::with_name("pe-rva")
+        .long("pe-rva")
+        .help("Report offsets in the PE section as relative virtual addresses (RVAs)")
+        .requires("pe-section")
+    )
+    .arg(
+      Arg::with_name("hex-input")
+        .long("hex-input")
+        .help("Decode the input from hex digits before searching, ignoring whitespace")
+        .conflicts_with("offset")
+    )
+    .arg(
+      Arg::with_name("device")
+        .long("device")
+        .help("Read the input in chunks, instead of loading it entirely in memory, as \
+               required for block devices. Anchors and matches longer than --max-match-bytes \
+               are unreliable across chunks")
+        .conflicts_with_all(&[
+          "invert-match",
+          "mask",
+          "literal",
+          "within",
+          "record-format",
+          "hex-input",
+        ])
+    )
+    .arg(
+      Arg::with_name("max-match-bytes")
+        .long("max-match-bytes")
+        .value_name("size")
+        .help("The length of the longest match that is guaranteed to be found when reading in \
+               chunks (default 4096). Accepts the K, M and G suffixes")
+        .validator(|size| parse_size(&size).map(|_| ()))
+        .requires("chunked")
+    )
+    .arg(
+      Arg::with_name("chunk-size")
+        .long("chunk-size")
+        .value_name("size")
+        .help("The size of the chunks read at once (default 1M). Must be larger than \
+               --max-match-bytes. Accepts the K, M and G suffixes")
+        .validator(|size| parse_size(&size).map(|_| ()))
+        .requires("chunked")
+    )
+    .arg(
+      Arg::with_name("stride")
+        .long("stride")
+        .value_name("n")
+        .help("Search only every nth chunk, skipping the others, for a fast approximate scan \
+               of huge inputs. Offsets remain absolute. Matches in the skipped chunks, or \
+               crossing into them, are missed")
+        .validator(
+          |stride| match parse_number(&stride)? {
+            0 => Err("the stride must be positive".to_owned()),
+            _ => Ok(())
+          }
+        )
+        .requires("chunked")
+    )
+    .arg(
+      Arg::with_name("timeout")
+        .long("timeout")
+        .alias("per-file-timeout")
+        .value_name("ms")
+        .help("Abort the search of a file that takes longer than the given milliseconds, and \
+               move on to the next file. The abandoned file counts as an error. The input is \
+               read in chunks, as with --device, and the limit is checked between chunks")
+        .validator(|ms| parse_number(&ms).map(|_| ()))
+        .conflicts_with_all(&[
+          "invert-match",
+          "mask",
+          "literal",
+          "within",
+          "record-format",
+          "hex-input",
+        ])
+    )
+    .arg(
+      Arg::with_name("max-memory")
+        .long("max-memory")
+        .value_name("size")
+        .help("Read the files larger than the given size in chunks, as with --device, with \
+               chunks small enough for the buffer to fit. Accepts the K, M and G suffixes")
+        .validator(|size| parse_size(&size).map(|_| ()))
+        .conflicts_with_all(&[
+          "invert-match",
+          "mask",
+          "literal",
+          "within",
+          "record-format",
+          "hex-input",
+        ])
+    )
+    .arg(
+      Arg::with_name("offset")
+        .long("offset")
+        .value_name("offset")
+        .help("Start searching at the given offset of each file. Accepts decimal and 0x \
+               prefixed hex. Reported offsets remain absolute")
+        .validator(|offset| parse_number(&offset).map(|_| ()))
+    )
+    .arg(
+      Arg::with_name("reverse")
+        .long("reverse")
+        .help("Process the files in reverse order")
+    )
+    .arg(
+      Arg::with_name("max-matches-total")
+        .long("max-matches-total")
+        .value_name("count")
+        .help("Stop after outputting the given number of matches, across all files. With \
+               --resume-from, the file where the limit is reached is searched again when \
+               resuming")
+        .validator(|count| parse_number(&count).map(|_| ()))
+        .requires("per-match")
+        .conflicts_with("invert-match")
+    )
+    .arg(
+      Arg::with_name("require-match")
+        .long("require-match")
+        .help("Fail with exit status 6 if no file matched, instead of 1")
+    )
+    .arg(
+      Arg::with_name("no-match-exit-zero")
+        .long("no-match-exit-zero")
+        .help("Exit with status 0 if no file matched, instead of 1. Errors still fail")
+        .conflicts_with_all(&["require-match", "all-match"])
+    )
+    .arg(
+      Arg::with_name("all-match")
+        .long("all-match")
+        .help("Succeed only if every searched file matched, reporting the files without a \
+               match to stderr")
+    )
+    .arg(
+      Arg::with_name("fail-fast")
+        .long("fail-fast")
+        .help("Stop at the first file that fails to be read. By default, the remaining files \
+               are still processed. Files abandoned by --timeout don't stop the search")
+    )
+    .group(
+      // The output modes that output each match on its own.
+      ArgGroup::with_name("per-match")
+        .args(&[
+          "only-matching",
+          "byte-offset",
+          "format",
+          "binary-output",
+          "json-array",
+          "hexdump",
+          "extract",
+          "bytes-hex",
+          "context-hex",
+          "r2-commands",
+        ])
+        .multiple(true)
+    )
+    .group(
+      // The flags that read the input in chunks.
+      ArgGroup::with_name("chunked")
+        .args(&["device", "timeout", "max-memory"])
+        .multiple(true)
+    )
+    .arg(
+      Arg::with_name("no-buffer-reuse")
+        .long("no-buffer-reuse")
+        .help("Allocate a fresh buffer for each file, instead of reusing the same one. Meant \
+               for debugging, as it is slower")
+    )
+    .arg(
+      Arg::with_name("resume-from")
+        .long("resume-from")
+        .value_name("checkpoint")
+        .help("Save the position of the scan to the given file, and resume from it when it \
+               exists. The files must be given in the same order. Positions within a file \
+               are only saved when reading in chunks. Removed when the scan completes")
+    )
+    .arg(
+      Arg::with_name("type")
+        .long("type")
+        .value_name("extensions")
+        .help("Search only the files with one of the given comma separated extensions, as in \
+               bin,exe,dll, ignoring case. Other files are skipped without being opened. \
+               Stdin is always searched")
+    )
+    .arg(
+      Arg::with_name("only-files-changed")
+        .long("only-files-changed")
+        .value_name("state")
+        .help("Skip the files whose modification time is unchanged since the last scan, as \
+               recorded in the given state file. The state file is then updated with the \
+               files that were searched")
+    )
     // Output flags:
+    .arg(
+      Arg::with_name("merge-adjacent")
+        .long("merge-adjacent")
+        .help("Coalesce matches that end where the next one starts. Offsets are printed as \
+               the combined start-end range")
+        .conflicts_with_all(&["invert-match", "show-pattern", "device", "timeout", "max-memory"])
+    )
+    .arg(
+      Arg::with_name("detect-encoding")
+        .long("detect-encoding")
+        .help("Annotate each offset with the guessed encoding of the matched bytes: ascii, \
+               utf-8, utf-16le, utf-16be or binary")
+    )
+    .arg(
+      Arg::with_name("utf16-preview")
+        .long("utf16-preview")
+        .help("Annotate each offset that lies in UTF-16 text with the decoded text around it, \
+               as in 0x10 (utf-16le \"Hello\"). Only ASCII range characters are recognized")
+        .requires("byte-offset")
+    )
+    .arg(
+      Arg::with_name("gaps")
+        .long("gaps")
+        .help("Print the start-end offset ranges of the gaps between matches, including before \
+               the first and after the last match. As with --invert-match, the exit status \
+               tells whether any gap was printed, so a file without matches is a single gap \
+               and succeeds, while a file covered by matches fails")
+        .requires("byte-offset")
+        .conflicts_with_all(&[
+          "invert-match",
+          "offset-from-end",
+          "max-matches-total",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("relative-to")
+        .long("relative-to")
+        .value_name("anchor")
+        .help("Print each offset relative to the nearest preceding match of the anchor \
+               pattern, as in #2+0x1c for 0x1c bytes after the third anchor. Matches before \
+               the first anchor are not reported")
+        .requires("byte-offset")
+        .conflicts_with_all(&[
+          "invert-match",
+          "mask",
+          "literal",
+          "gaps",
+          "merge-adjacent",
+          "offset-from-end",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("with-length")
+        .long("with-length")
+        .help("Follow each offset with the length of the match, as in 0x10 (12 bytes)")
+        .requires("byte-offset")
+    )
+    .arg(
+      Arg::with_name("offset-from-end")
+        .long("offset-from-end")
+        .help("Print offsets backward from the end of the input, as in -0x10 for a match 16 \
+               bytes before the end")
+        .requires("byte-offset")
+        .conflicts_with_all(&[
+          "merge-adjacent",
+          "within",
+          "pe-section",
+          "record-format",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("span")
+        .long("span")
+        .help("Print only the start of the first match and the end of the last match of each \
+               file, as in 0x100 - 0x5a00")
+        .requires("byte-offset")
+        .conflicts_with_all(&[
+          "invert-match",
+          "gaps",
+          "merge-adjacent",
+          "offset-group",
+          "relative-to",
+          "offset-from-end",
+          "with-length",
+          "detect-encoding",
+          "utf16-preview",
+          "offset-preview",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("last")
+        .long("last")
+        .help("Print only the offset of the last match of each file")
+        .requires("byte-offset")
+        .conflicts_with_all(&[
+          "invert-match",
+          "gaps",
+          "merge-adjacent",
+          "offset-group",
+          "span",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("offset-group")
+        .long("offset-group")
+        .value_name("group")
+        .help("Print the offset of the given capture group instead of the whole match, \
+               skipping the matches where the group didn't participate. Groups are numbered \
+               within each pattern")
+        .validator(|group| parse_number(&group).map(|_| ()))
+        .requires("byte-offset")
+        .conflicts_with_all(&["invert-match", "mask", "literal", "gaps", "merge-adjacent"])
+    )
+    .arg(
+      Arg::with_name("offset-width")
+        .long("offset-width")
+        .value_name("digits")
+        .help("Zero pad offsets to the given number of hex digits, as in 0x0000abcd. With \
+               'auto', the width is that of the input size, when known")
+        .validator(|width| if width == "auto" { Ok(()) } else { parse_number(&width).map(|_| ()) })
+        .requires("byte-offset")
+    )
+    .arg(
+      Arg::with_name("offset-separator")
+        .long("offset-separator")
+        .value_name("separator")
+        .help("Separate offsets with the given string, as in ',', instead of ending each with \
+               a newline, to print them all on a single line")
+        .allow_hyphen_values(true)
+        .requires("byte-offset")
+        .conflicts_with_all(&["span", "output-by-pattern"])
+    )
+    .arg(
+      Arg::with_name("offset-preview")
+        .long("offset-preview")
+        .value_name("bytes")
+        .help("Follow each offset with up to the given number of matched bytes, in hex")
+        .validator(|bytes| parse_number(&bytes).map(|_| ()))
+        .requires("byte-offset")
+    )
+    .arg(
+      Arg::with_name("line-buffered")
+        .long("line-buffered")
+        .help("Flush the output after each match, for interactive pipelines")
+    )
+    .arg(
+      Arg::with_name("report")
+        .long("report")
+        .help("Print the number of matches, searched files and elapsed time to stderr")
+    )
+    .arg(
+      Arg::with_name("count-bytes-total")
+        .long("count-bytes-total")
+        .help("Print the total number of bytes read from all files to stderr, as a human \
+               readable size, as in 1.5 MiB")
+    )
+    .arg(
+      Arg::with_name("si")
+        .long("si")
+        .help("Print sizes in powers of 1000, as in 1.5 MB, instead of 1024")
+        .requires("count-bytes-total")
+    )
+    .arg(
+      Arg::with_name("summary-json")
+        .long("summary-json")
+        .help("Print a JSON object to stderr at the end, with the number of searched files, \
+               matched files, errors, bytes read, matches and elapsed seconds")
+    )
+    .arg(
+      Arg::with_name("summary-file")
+        .long("summary-file")
+        .value_name("path")
+        .help("Write the JSON summary to the given file, instead of stderr")
+        .requires("summary-json")
+    )
+    .arg(
+      Arg::with_name("output-by-pattern")
+        .long("output-by-pattern")
+        .value_name("dir")
+        .help("Search each pattern on its own, writing its output to its own file in the \
+               given directory, as in p0.txt for the first pattern, instead of stdout. Stdin \
+               can't be searched this way")
+        .conflicts_with_all(&[
+          "manifest",
+          "mask",
+          "literal",
+          "show-pattern",
+          "count-breakdown",
+          "offset-histogram",
+          "json-array",
+          "count-files",
+          "extract",
+          "resume-from",
+          "top",
+          "group-by-value",
+        ])
+    )
+    .arg(
+      Arg::with_name("progress")
+        .long("progress")
+        .help("Report the progress of each file to stderr. Ignored if stderr is not a terminal")
+    )
+    .arg(
+      Arg::with_name("show-pattern")
+        .long("show-pattern")
+        .help("Prefix each match with the index of the pattern that produced it, as in [p0]")
+        .conflicts_with("invert-match")
+    )
+    .arg(
+      Arg::with_name("concat")
+        .long("concat")
+        .help("Print the matched bytes back to back, without file names nor newlines, to \
+               reassemble the matched content")
+        .requires("only-matching")
+        .conflicts_with_all(&["hash", "show-pattern"])
+    )
+    .arg(
+      Arg::with_name("pad-to")
+        .long("pad-to")
+        .value_name("width")
+        .help("Pad each printed match shorter than the given width with the --pad-byte")
+        .validator(|width| parse_number(&width).map(|_| ()))
+        .requires("only-matching")
+        .conflicts_with_all(&["invert-match", "hash"])
+    )
+    .arg(
+      Arg::with_name("pad-byte")
+        .long("pad-byte")
+        .value_name("hex")
+        .help("The byte to pad matches with, as two hex digits (default 20, a space)")
+        .validator(|byte| parse_byte(&byte).map(|_| ()))
+        .requires("pad-to")
+    )
+    .arg(
+      Arg::with_name("context")
+        .short("C")
+        .long("context")
+        .value_name("bytes")
+        .help("Print the given number of bytes before and after each match. Overlapping \
+               contexts are merged. Context stops at the bounds of the enclosing record or \
+               --within region")
+        .validator(|bytes| parse_number(&bytes).map(|_| ()))
+        .requires("only-matching")
+        .conflicts_with_all(&[
+          "invert-match",
+          "merge-adjacent",
+          "hash",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("before-context-bytes")
+        .long("before-context-bytes")
+        .value_name("bytes")
+        .help("Print the given number of bytes before each match. Overrides --context")
+        .validator(|bytes| parse_number(&bytes).map(|_| ()))
+        .requires("only-matching")
+        .conflicts_with_all(&[
+          "invert-match",
+          "merge-adjacent",
+          "hash",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("after-context-bytes")
+        .long("after-context-bytes")
+        .value_name("bytes")
+        .help("Print the given number of bytes after each match. Overrides --context")
+        .validator(|bytes| parse_number(&bytes).map(|_| ()))
+        .requires("only-matching")
+        .conflicts_with_all(&[
+          "invert-match",
+          "merge-adjacent",
+          "hash",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+    )
+    .arg(
+      Arg::with_name("hash")
+        .long("hash")
+        .value_name("algorithm")
+        .help("Print the hex digest of the matched bytes instead of the bytes themselves")
+        .possible_values(&["md5", "sha1", "sha256"])
+        .requires("only-matching")
+    )
     .arg(
       Arg::with_name("with-filename")
         .short("H")
@@ -119,94 +1142,716 @@ fn build_app() -> App<'static, 'static> {
           "files-without-matches",
         ])
     )
+    .arg(
+      Arg::with_name("absolute-paths")
+        .long("absolute-paths")
+        .help("Print the files with their canonical absolute path, instead of as given. Stdin \
+               keeps its label")
+    )
+    .arg(
+      Arg::with_name("stdin-filename")
+        .long("stdin-filename")
+        .value_name("name")
+        .help("Label the input read from stdin with the given name, instead of <stdin>")
+    )
     .arg(
       Arg::with_name("only-matching")
         .short("o")
         .long("only-matching")
         .help("Prints the matched bytes of each match")
-        .overrides_with_all(&[
-          "byte-offset",
-          "files-with-matches",
-          "files-without-matches",
-        ])
+        .overrides_with_all(OUTPUT_MODES)
     )
     .arg(
       Arg::with_name("byte-offset")
         .short("b")
         .long("byte-offset")
         .help("Prints the byte offset of each match")
-        .overrides_with_all(&[
-          "only-matching",
-          "files-with-matches",
-          "files-without-matches",
-        ])
+        .overrides_with_all(OUTPUT_MODES)
     )
     .arg(
       Arg::with_name("files-with-matches")
         .short("l")
         .long("files-with-matches")
         .help("Prints the name of the matched files (default output mode)")
-        .overrides_with_all(&[
-          "only-matching",
-          "byte-offset",
-          "files-without-matches",
-        ])
+        .overrides_with_all(OUTPUT_MODES)
     )
     .arg(
       Arg::with_name("files-without-matches")
         .short("L")
         .long("files-without-matches")
-        .help("Prints the name of non-matched files")
-        .overrides_with_all(&[
+        .alias("files-without-match")
+        .help("Prints the name of the files that don't contain the pattern")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("dry-match")
+        .long("dry-match")
+        .help("Prints the name of every file, followed by whether it matched")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("offset-histogram")
+        .long("offset-histogram")
+        .help("Prints how many times each offset matched across all files, sorted by offset")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("group-by-value")
+        .long("group-by-value")
+        .help("Prints how many times each byte value was matched across all files, sorted by \
+               count, as in 0x4d: 12. Meant for single byte patterns, as every matched byte \
+               is counted")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("format")
+        .long("format")
+        .value_name("template")
+        .help("Prints each match according to the template. Supports %p (path), %o (hex offset), \
+               %d (decimal offset), %h (hex bytes), %b (raw bytes), %l (match length), \
+               %0 to %9 (capture groups) and %% (a literal %). Groups that don't exist in the \
+               pattern, or that didn't participate in the match, expand to an empty string")
+        .validator(|template| format::parse(template).map(|_| ()))
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("replace-file")
+        .long("replace-file")
+        .value_name("path")
+        .help("Prints the input with every match replaced by the contents of the given file")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("binary-output")
+        .long("binary-output")
+        .help("Prints each match as a binary record: the offset as u64 little endian, the \
+               length as u32 little endian, and the matched bytes")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("bytes-hex")
+        .long("bytes-hex")
+        .help("Prints the matched bytes of each match in lowercase hex, one match per line")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("context-hex")
+        .long("context-hex")
+        .value_name("bytes")
+        .help("Prints each match in lowercase hex with the given number of bytes before and \
+               after it, with the match delimited by '|', as in 0x10: 00ff|4d5a|9000")
+        .validator(|bytes| parse_number(&bytes).map(|_| ()))
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("hexdump")
+        .long("hexdump")
+        .help("Prints each match as a hexdump, with the absolute offset, hex bytes and ASCII \
+               columns")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("hex-cols")
+        .long("hex-cols")
+        .value_name("count")
+        .help("The number of bytes per row of the hexdump, a multiple of 8 [default: 16]")
+        .validator(
+          |cols| match parse_number(&cols)? {
+            cols if cols > 0 && cols % 8 == 0 => Ok(()),
+            _ => Err("the number of bytes per row must be a positive multiple of 8".to_owned())
+          }
+        )
+        .requires("hexdump")
+    )
+    .arg(
+      Arg::with_name("hex-row-sep")
+        .long("hex-row-sep")
+        .value_name("hex")
+        .help("The byte terminating each line of the hexdump, as two hex digits (default 0a, \
+               a newline). Use 00 for NUL separated output")
+        .validator(|byte| parse_byte(&byte).map(|_| ()))
+        .requires("hexdump")
+    )
+    .arg(
+      Arg::with_name("json-array")
+        .long("json-array")
+        .help("Prints a single JSON array with an object per match, across all files, with \
+               the path, offset, length and hex bytes of the match")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("r2-commands")
+        .long("r2-commands")
+        .help("Prints a radare2 command per match, creating a flag at its offset, as in \
+               f match.0 @ 0x10. Flags are numbered across all files")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("count")
+        .short("c")
+        .long("count")
+        .help("Prints the number of matches of each file, including 0 for the files without \
+               matches, as grep -c does")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("count-files")
+        .long("count-files")
+        .help("Prints only the number of files that matched, across all files")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("top")
+        .long("top")
+        .value_name("count")
+        .help("Print only the given number of files with the most matches, with their \
+               number of matches, sorted by it in descending order, once all files are \
+               searched")
+        .validator(
+          |count| match parse_number(&count)? {
+            0 => Err("the number of files must be positive".to_owned()),
+            _ => Ok(())
+          }
+        )
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("strings")
+        .long("strings")
+        .help("Search each run of printable ASCII on its own, as found by the strings tool, \
+               and print the offset and contents of each run that matches")
+        .conflicts_with_all(&["invert-match", "device", "timeout", "max-memory"])
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("min-string-len")
+        .long("min-string-len")
+        .value_name("bytes")
+        .help("The minimum length of the runs searched by --strings (default 4)")
+        .validator(|bytes| parse_number(&bytes).map(|_| ()))
+        .requires("strings")
+    )
+    .arg(
+      Arg::with_name("cstring")
+        .long("cstring")
+        .help("Search each NUL terminated string on its own, and print the offset and \
+               contents of each string that matches")
+        .conflicts_with_all(&["invert-match", "device", "timeout", "max-memory"])
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("extract")
+        .long("extract")
+        .value_name("dir")
+        .help("Write each match to its own file in the given directory, named after the file \
+               and offset of the match, as in file.bin_0x10.bin, and print the written paths")
+        .conflicts_with("invert-match")
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("probe-offsets")
+        .long("probe-offsets")
+        .value_name("path")
+        .help("Prints whether the pattern matches starting at each of the hex offsets listed \
+               in the given file, separated by whitespace")
+        .conflicts_with_all(&[
+          "invert-match",
+          "mask",
+          "literal",
+          "within",
+          "record-format",
+          "device",
+          "timeout",
+          "max-memory",
+        ])
+        .overrides_with_all(OUTPUT_MODES)
+    )
+    .arg(
+      Arg::with_name("with-count")
+        .long("with-count")
+        .help("Follow each listed file name with its number of matches, as in path: 5")
+        .conflicts_with_all(&[
           "only-matching",
           "byte-offset",
-          "files-with-matches",
+          "dry-match",
+          "offset-histogram",
+          "format",
+          "replace-file",
+          "binary-output",
+          "count",
+          "probe-offsets",
+          "device",
+          "timeout",
+          "max-memory",
         ])
     )
+    .arg(
+      Arg::with_name("min-count")
+        .long("min-count")
+        .value_name("count")
+        .help("List only the files with at least the given number of matches")
+        .validator(|count| parse_number(&count).map(|_| ()))
+        .requires("files-with-matches")
+        .conflicts_with_all(&["mask", "literal", "device", "timeout", "max-memory"])
+    )
+    .arg(
+      Arg::with_name("count-breakdown")
+        .long("count-breakdown")
+        .help("Prints the count of each pattern on its own, as in p0=3 p1=0. Patterns are \
+               counted independently, so their matches may overlap")
+        .requires("count")
+        .conflicts_with("invert-match")
+    )
+    .arg(
+      Arg::with_name("count-total")
+        .long("count-total")
+        .help("Follow the counts of the files with a total: N line, summing all the counts")
+        .requires("count")
+    )
 }
 
 
 /// Build an `Args` from clap's `ArgMatches`.
-/// The matches are supposed to be valid, therefore there is no error handling/reporting.
-fn build_args(args: ArgMatches) -> Args {
-  let pattern = args.value_of("pattern")
-                    .expect("<pattern> not in ArgMatches") // pattern is required.
-                    .to_owned();
+/// The matches are supposed to be valid, therefore errors are only reported for what
+/// clap can't validate, like environment variables.
+fn build_args(args: ArgMatches) -> Result<Args, Error> {
+  let mut files: Vec<PathBuf> = args.values_of_os("files")
+                                   .expect("<files> not in ArgMatches")
+                                   .map(PathBuf::from)
+                                   .collect();
+
+  let mut patterns: Vec<String> =
+    if args.is_present("regexp") || args.is_present("pattern-spec") {
+      let specs = args.values_of("pattern-spec")
+                      .into_iter()
+                      .flatten()
+                      .map(|spec| parse_pattern_spec(spec).expect("<spec> is validated by clap"));
 
-  let files: Box<[PathBuf]> = args.values_of_os("files")
-                                  .expect("<files> not in ArgMatches")
-                                  .map(PathBuf::from)
-                                  .collect();
+      args.values_of("regexp")
+          .into_iter()
+          .flatten()
+          .map(str::to_owned)
+          .chain(specs)
+          .collect()
+    }
+    else if let Some(var) = args.value_of_os("pattern-env") {
+      let pattern = env::var(var).map_err(
+        |e| Error {
+          message: format!(
+            "error: environment variable '{}' {}",
+            var.to_string_lossy(),
+            match e {
+              env::VarError::NotPresent    => "is not set",
+              env::VarError::NotUnicode(_) => "is not valid unicode"
+            }
+          )
+        }
+      )?;
+
+      vec![pattern]
+    }
+    else {
+      Vec::new()
+    };
+
+  let manifest = match args.value_of_os("manifest") {
+    None => None,
+    Some(path) => {
+      let error = |message: String| Error {
+        message: format!(
+          "error: failed to read manifest '{}', {}",
+          path.to_string_lossy(),
+          message
+        )
+      };
+
+      let manifest = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+      let entries = manifest::parse(&manifest).map_err(error)?;
+
+      Some(entries.into_boxed_slice())
+    }
+  };
+
+  if patterns.is_empty() && manifest.is_none() {
+    patterns.push(
+      args.value_of("pattern")
+          .expect("<pattern> not in ArgMatches") // pattern is required without -e.
+          .to_owned()
+    );
+  }
+  else if let Some(file) = args.value_of_os("pattern") {
+    // When patterns are given otherwise, the first positional argument is a file.
+    if args.occurrences_of("files") == 0 {
+      files.clear(); // Discard the default value.
+    }
+
+    files.insert(0, PathBuf::from(file));
+  }
+
+  if args.is_present("glob") {
+    for pattern in &mut patterns {
+      *pattern = hex::glob_to_regex(&pattern).map_err(
+        |position| Error {
+          message: format!("error: invalid byte glob '{}' at byte {}", pattern, position)
+        }
+      )?;
+    }
+  }
+
+  if args.is_present("utf16le") || args.is_present("utf16be") {
+    for pattern in &mut patterns {
+      *pattern = encoding::utf16_regex(pattern, args.is_present("utf16be"));
+    }
+  }
+
+  let patterns = patterns.into_boxed_slice();
+
+  // Each pattern reads the files again, which stdin doesn't allow.
+  if args.is_present("output-by-pattern") && files.iter().any(|file| file == Path::new(STDIN)) {
+    return Err(Error {
+      message: "error: --output-by-pattern can't search stdin, as each pattern reads the \
+                input again"
+                 .to_owned()
+    });
+  }
+
+  let files = files.into_boxed_slice();
 
   let flag = |f| args.is_present(f);
 
-  let output_flags = (
-    flag("only-matching"),
-    flag("byte-offset"),
-    flag("files-with-matches"),
-    flag("files-without-matches")
-  );
+  let mask = match args.value_of("mask") {
+    None => None,
+    Some(mask) => {
+      let mask = hex::decode(mask).expect("<mask> is validated by clap");
 
-  let output = match output_flags {
-    (true, _, _, _) => Output::Bytes,
-    (_, true, _, _) => Output::Offset,
-    (_, _, true, _) => Output::FileName,
-    (_, _, _, true) => Output::FileName,
-    (_, _, _, _)    => Default::default(),
+      let bytes = hex::decode(&patterns[0]).map_err(
+        |position| Error {
+          message: format!("error: invalid hex in pattern at byte {}", position)
+        }
+      )?;
+
+      if bytes.len() != mask.len() || bytes.is_empty() {
+        return Err(Error {
+          message: "error: the pattern and the mask must have the same, non zero, length"
+                     .to_owned()
+        });
+      }
+
+      Some(Mask { bytes, mask })
+    }
   };
 
-  Args {
+  let literal =
+    if flag("literal") {
+      let bytes = hex::decode(&patterns[0]).map_err(
+        |position| Error {
+          message: format!("error: invalid hex in pattern at byte {}", position)
+        }
+      )?;
+
+      if bytes.is_empty() {
+        return Err(Error { message: "error: the literal must not be empty".to_owned() });
+      }
+
+      Some(Literal::new(bytes, flag("also-reversed")))
+    } else {
+      None
+    };
+
+  let context = |arg| args.value_of(arg)
+                           .or_else(|| args.value_of("context"))
+                           .map(|bytes| parse_number(bytes).expect("<bytes> is validated by clap"))
+                           .unwrap_or(0);
+
+  let record_format = args.value_of("record-format").map(
+    |format| match format {
+      "netstring" => RecordFormat::Netstring,
+      "len32le"   => RecordFormat::Len32Le,
+      _ => unreachable!("invalid <record-format> value")
+    }
+  );
+
+  let size = |arg, default| args.value_of(arg)
+                                .map(|size| parse_size(size).expect("<size> is validated by clap"))
+                                .unwrap_or(default);
+
+  let max_match_bytes = size("max-match-bytes", DEFAULT_MAX_MATCH_BYTES);
+  let mut chunk_size = size("chunk-size", DEFAULT_CHUNK_SIZE);
+
+  let max_memory = args.value_of("max-memory")
+                       .map(|size| parse_size(size).expect("<size> is validated by clap"));
+
+  // The buffer holds a chunk besides the carried over match, so both must fit the budget.
+  if let Some(max_memory) = max_memory {
+    if max_memory <= max_match_bytes {
+      return Err(Error {
+        message: format!(
+          "error: the max memory ({}) must be larger than the max match bytes ({}), to fit \
+           a chunk",
+          max_memory,
+          max_match_bytes
+        )
+      });
+    }
+
+    chunk_size = chunk_size.min(max_memory - max_match_bytes);
+  }
+
+  // Each chunk must have room for a match to be carried over, besides new bytes.
+  if chunk_size <= max_match_bytes {
+    return Err(Error {
+      message: format!(
+        "error: the chunk size ({}) must be larger than the max match bytes ({})",
+        chunk_size,
+        max_match_bytes
+      )
+    });
+  }
+
+  let output =
+    if let Some(template) = args.value_of("format") {
+      Output::Format(
+        format::parse(template).expect("<format> is validated by clap")
+      )
+    }
+    else if flag("strings") {
+      let min_len = args.value_of("min-string-len")
+                        .map_or(4, |len| parse_number(len).expect("<bytes> is validated by clap"));
+
+      Output::Strings(min_len)
+    }
+    else if let Some(dir) = args.value_of_os("extract") {
+      Output::Extract(PathBuf::from(dir))
+    }
+    else if let Some(path) = args.value_of_os("probe-offsets") {
+      let error = |message: String| Error {
+        message: format!(
+          "error: failed to read offsets file '{}', {}",
+          path.to_string_lossy(),
+          message
+        )
+      };
+
+      let offsets = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+
+      let offsets = offsets.split_whitespace()
+                           .map(
+                             |offset| {
+                               let digits = offset.trim_start_matches("0x");
+                               usize::from_str_radix(digits, 16).map_err(
+                                 |_| error(format!("invalid hex offset '{}'", offset))
+                               )
+                             }
+                           )
+                           .collect::<Result<Box<[usize]>, _>>()?;
+
+      Output::Probe(offsets)
+    }
+    else if let Some(path) = args.value_of_os("replace-file") {
+      // The replacement is read only once, for all files.
+      let replacement = fs::read(path).map_err(
+        |e| Error {
+          message: format!(
+            "error: failed to read replacement file '{}', {}",
+            path.to_string_lossy(),
+            e
+          )
+        }
+      )?;
+
+      Output::Replace(replacement)
+    }
+    else if let Some(count) = args.value_of("top") {
+      Output::Top(parse_number(count).expect("<count> is validated by clap"))
+    }
+    else if let Some(bytes) = args.value_of("context-hex") {
+      Output::ContextHex(parse_number(bytes).expect("<bytes> is validated by clap"))
+    }
+    else if flag("group-by-value") {
+      Output::ValueHistogram
+    }
+    else if flag("r2-commands") {
+      Output::R2Commands
+    }
+    else if flag("hexdump") {
+      let cols = args.value_of("hex-cols")
+                     .map_or(16, |cols| parse_number(cols).expect("<count> is validated by clap"));
+
+      Output::Hexdump(cols)
+    }
+    else if flag("only-matching") {
+      Output::Bytes
+    }
+    else if flag("byte-offset") {
+      Output::Offset
+    }
+    else if flag("dry-match") {
+      Output::Status
+    }
+    else if flag("offset-histogram") {
+      Output::Histogram
+    }
+    else if flag("binary-output") {
+      Output::Binary
+    }
+    else if flag("count") {
+      Output::Count
+    }
+    else if flag("json-array") {
+      Output::JsonArray
+    }
+    else if flag("count-files") {
+      Output::CountFiles
+    }
+    else if flag("cstring") {
+      Output::CString
+    }
+    else if flag("bytes-hex") {
+      Output::BytesHex
+    }
+    else { // Both with and without matches, files are listed by name.
+      Output::FileName
+    };
+
+  Ok(Args {
     options: Options {
       inverse: flag("invert-match"),
       case_insensitive: flag("ignore-case"),
+      literal_newline: flag("literal-newline"),
+      wrap_prefix: args.value_of("wrap-prefix").unwrap_or_default().to_owned(),
+      wrap_suffix: args.value_of("wrap-suffix").unwrap_or_default().to_owned(),
+      allow_empty_pattern: flag("allow-empty-pattern"),
+      dfa_limit: args.value_of("dfa-limit")
+                     .map(|size| parse_size(size).expect("<size> is validated by clap")),
+      not_followed_by: args.value_of("not-followed-by").map(str::to_owned),
+      backrefs: args.values_of("backref")
+                    .into_iter()
+                    .flatten()
+                    .map(|backref| parse_backref(backref).expect("<a=b> is validated by clap"))
+                    .collect(),
+      word_bytes: args.value_of("word-bytes")
+                      .map(|set| parse_byte_set(set).expect("<set> is validated by clap")),
+      prefilter: args.value_of("prefilter")
+                     .map(|byte| parse_byte(byte).expect("<byte> is validated by clap")),
       trim_ending_newline: flag("trim-ending-newline"),
+      hex_input: flag("hex-input"),
+      device: flag("device"),
+      max_match_bytes,
+      chunk_size,
+      max_memory,
+      stride: args.value_of("stride")
+                  .map_or(1, |stride| parse_number(stride).expect("<n> is validated by clap")),
+      timeout: args.value_of("timeout")
+                   .map(|ms| parse_number(ms).expect("<ms> is validated by clap"))
+                   .map(|ms| Duration::from_millis(ms as u64)),
+      offset: args.value_of("offset")
+                  .map(|offset| parse_number(offset).expect("<offset> is validated by clap"))
+                  .unwrap_or(0),
+      reverse: flag("reverse"),
+      remaining_matches: args.value_of("max-matches-total")
+                             .map(|count| parse_number(count).expect("<count> is validated"))
+                             .map(Cell::new),
+      require_match: flag("require-match"),
+      all_match: flag("all-match"),
+      no_match_exit_zero: flag("no-match-exit-zero"),
+      fail_fast: flag("fail-fast"),
+      no_buffer_reuse: flag("no-buffer-reuse"),
+      resume_from: args.value_of_os("resume-from").map(PathBuf::from),
+      only_files_changed: args.value_of_os("only-files-changed").map(PathBuf::from),
+      types: args.value_of("type").map(
+        |types| types.split(',').map(str::to_ascii_lowercase).collect()
+      ),
       non_matching: flag("files-without-matches"),
-      print_filename: flag("with-filename") || !(flag("no-filename") || files.len() == 1),
+      // Records are always labeled, except with (-h).
+      print_filename: flag("with-filename")
+                   || !(flag("no-filename") || (files.len() == 1 && record_format.is_none())),
+      absolute_paths: flag("absolute-paths"),
+      stdin_filename: args.value_of("stdin-filename").map(str::to_owned),
+      show_pattern: flag("show-pattern"),
+      with_count: flag("with-count"),
+      min_count: args.value_of("min-count")
+                     .map(|count| parse_number(count).expect("<count> is validated by clap")),
+      count_breakdown: flag("count-breakdown"),
+      count_total: flag("count-total"),
+      pad_to: args.value_of("pad-to")
+                  .map(|width| parse_number(width).expect("<width> is validated by clap"))
+                  .unwrap_or(0),
+      concat: flag("concat"),
+      pad_byte: args.value_of("pad-byte")
+                    .map(|byte| parse_byte(byte).expect("<hex> is validated by clap"))
+                    .unwrap_or(b' '),
+      hex_row_sep: args.value_of("hex-row-sep")
+                       .map(|byte| parse_byte(byte).expect("<hex> is validated by clap"))
+                       .unwrap_or(b'\n'),
+      before_context: context("before-context-bytes"),
+      after_context: context("after-context-bytes"),
+      hash: args.value_of("hash").map(
+        |algorithm| match algorithm {
+          "md5"    => digest::Algorithm::Md5,
+          "sha1"   => digest::Algorithm::Sha1,
+          "sha256" => digest::Algorithm::Sha256,
+          _ => unreachable!("invalid <hash> value")
+        }
+      ),
+      merge_adjacent: flag("merge-adjacent"),
+      detect_encoding: flag("detect-encoding"),
+      utf16_preview: flag("utf16-preview"),
+      gaps: flag("gaps"),
+      with_length: flag("with-length"),
+      offset_from_end: flag("offset-from-end"),
+      offset_separator: args.value_of("offset-separator").map(str::to_owned),
+      span: flag("span"),
+      last: flag("last"),
+      offset_group: args.value_of("offset-group")
+                        .map(|group| parse_number(group).expect("<group> is validated by clap")),
+      offset_width: args.value_of("offset-width").map(
+        |width| match width {
+          "auto" => OffsetWidth::Auto,
+          width  => OffsetWidth::Fixed(parse_number(width).expect("<digits> is validated by clap"))
+        }
+      ),
+      offset_preview: args.value_of("offset-preview")
+                          .map(|bytes| parse_number(bytes).expect("<bytes> is validated by clap"))
+                          .unwrap_or(0),
+      progress: flag("progress") && io::stderr().is_terminal(),
+      progress_window: Cell::new(None),
+      line_buffered: flag("line-buffered"),
+      report: flag("report"),
+      count_bytes_total: flag("count-bytes-total"),
+      si: flag("si"),
+      summary_json: flag("summary-json"),
+      summary_file: args.value_of_os("summary-file").map(PathBuf::from),
+      output_by_pattern: args.value_of_os("output-by-pattern").map(PathBuf::from),
+      pe_section: args.value_of("pe-section").map(str::to_owned),
+      pe_rva: flag("pe-rva"),
+      record_format,
+      mask,
+      literal,
+      relative_to: args.value_of("relative-to").map(String::from),
+      within: args.values_of("within").map(
+        |mut delimiters| {
+          let mut delimiter = || delimiters.next()
+                                           .expect("<within> requires two values")
+                                           .to_owned();
+          (delimiter(), delimiter())
+        }
+      ),
       output
     },
-    pattern,
+    patterns,
+    manifest,
     files
-  }
+  })
 }
 
 
@@ -219,7 +1864,7 @@ pub fn parse<
   let app = build_app();
 
   match app.get_matches_from_safe(args) {
-    Ok(arg_matches) => Ok(Command::Grep(build_args(arg_matches))),
+    Ok(arg_matches) => build_args(arg_matches).map(|args| Command::Grep(Box::new(args))),
     Err(e) => match e.kind {
       clap::ErrorKind::HelpDisplayed    => Ok(Command::Help(e.message)),
       clap::ErrorKind::VersionDisplayed => Ok(Command::Version(e.message)),
@@ -0,0 +1,127 @@
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+
+/// The hash algorithms supported by `--hash`.
+#[derive(Debug, Clone, Copy)]
+pub enum Algorithm {
+  Md5,
+  Sha1,
+  Sha256
+}
+
+
+impl Algorithm {
+  /// Compute the digest of the given bytes.
+  pub fn digest(self, bytes: &[u8]) -> Vec<u8> {
+    match self {
+      Algorithm::Md5    => Md5::digest(bytes).to_vec(),
+      Algorithm::Sha1   => Sha1::digest(bytes).to_vec(),
+      Algorithm::Sha256 => Sha256::digest(bytes).to_vec()
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+
+  /// Check the digests of the given messages, and of runs of `a` whose lengths are around
+  /// the padding edges: 55 bytes leave room for the length in the last block, 56 don't.
+  fn check(algorithm: Algorithm, vectors: &[(&[u8], &str)], edges: &[(usize, &str)]) {
+    for (message, expected) in vectors {
+      assert_eq!(hex(&algorithm.digest(message)), *expected, "message {:?}", message);
+    }
+
+    for (len, expected) in edges {
+      assert_eq!(hex(&algorithm.digest(&vec![b'a'; *len])), *expected, "length {}", len);
+    }
+  }
+
+
+  #[test]
+  fn md5_rfc_1321() {
+    let vectors: &[(&[u8], &str)] = &[
+      (&b""[..], "d41d8cd98f00b204e9800998ecf8427e"),
+      (&b"a"[..], "0cc175b9c0f1b6a831c399e269772661"),
+      (&b"abc"[..], "900150983cd24fb0d6963f7d28e17f72"),
+      (&b"message digest"[..], "f96b697d7cb7938d525a2f31aaf161d0"),
+      (&b"abcdefghijklmnopqrstuvwxyz"[..], "c3fcd3d76192e4007dfb496cca67e13b"),
+      (
+        &b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"[..],
+        "d174ab98d277d9f5a5611c2c9f419d9f"
+      ),
+      (&b"1234567890".repeat(8)[..], "57edf4a22be3c955ac49da2e2107b67a"),
+    ];
+
+    let edges = [
+      (55, "ef1772b6dff9a122358552954ad0df65"),
+      (56, "3b0c8ac703f828b04c6c197006d17218"),
+      (63, "b06521f39153d618550606be297466d5"),
+      (64, "014842d480b571495a4a0363793f7367"),
+      (65, "c743a45e0d2e6a95cb859adae0248435"),
+      (119, "8a7bd0732ed6a28ce75f6dabc90e1613"),
+      (120, "5f61c0ccad4cac44c75ff505e1f1e537"),
+    ];
+
+    check(Algorithm::Md5, vectors, &edges);
+  }
+
+
+  #[test]
+  fn sha1_fips_180() {
+    let vectors: &[(&[u8], &str)] = &[
+      (&b"abc"[..], "a9993e364706816aba3e25717850c26c9cd0d89d"),
+      (&b""[..], "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+      (
+        &b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"[..],
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+      ),
+    ];
+
+    let edges = [
+      (55, "c1c8bbdc22796e28c0e15163d20899b65621d65a"),
+      (56, "c2db330f6083854c99d4b5bfb6e8f29f201be699"),
+      (63, "03f09f5b158a7a8cdad920bddc29b81c18a551f5"),
+      (64, "0098ba824b5c16427bd7a1122a5a442a25ec644d"),
+      (65, "11655326c708d70319be2610e8a57d9a5b959d3b"),
+      (119, "ee971065aaa017e0632a8ca6c77bb3bf8b1dfc56"),
+      (120, "f34c1488385346a55709ba056ddd08280dd4c6d6"),
+    ];
+
+    check(Algorithm::Sha1, vectors, &edges);
+  }
+
+
+  #[test]
+  fn sha256_fips_180() {
+    let vectors: &[(&[u8], &str)] = &[
+      (&b"abc"[..], "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+      (&b""[..], "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+      (
+        &b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"[..],
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+      ),
+    ];
+
+    let edges = [
+      (55, "9f4390f8d30c2dd92ec9f095b65e2b9ae9b0a925a5258e241c9f1e910f734318"),
+      (56, "b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a"),
+      (63, "7d3e74a05d7db15bce4ad9ec0658ea98e3f06eeecf16b4c6fff2da457ddc2f34"),
+      (64, "ffe054fe7ae0cb6dc65c3af9b61d5209f439851db43d0ba5997337df154668eb"),
+      (65, "635361c48bb9eab14198e76ea8ab7f1a41685d6ad62aa9146d301d4f17eb0ae0"),
+      (119, "31eba51c313a5c08226adf18d4a359cfdfd8d2e816b13f4af952f7ea6584dcfb"),
+      (120, "2f3d335432c70b580af0e8e1b3674a7c020d683aa5f73aaaedfdc55af904c21c"),
+    ];
+
+    check(Algorithm::Sha256, vectors, &edges);
+  }
+}
@@ -0,0 +1,110 @@
+use std::ops::Range;
+
+
+/// The guessed encoding of a byte region.
+#[derive(Debug, Clone, Copy)]
+pub enum Encoding {
+  Ascii,
+  Utf8,
+  Utf16Le,
+  Utf16Be,
+  Binary
+}
+
+
+impl Encoding {
+  pub fn name(self) -> &'static str {
+    match self {
+      Encoding::Ascii   => "ascii",
+      Encoding::Utf8    => "utf-8",
+      Encoding::Utf16Le => "utf-16le",
+      Encoding::Utf16Be => "utf-16be",
+      Encoding::Binary  => "binary"
+    }
+  }
+}
+
+
+/// Whether the byte is printable ASCII or common whitespace.
+fn is_text(b: u8) -> bool {
+  b.is_ascii_graphic() || b == b' ' || b == b'\t' || b == b'\r' || b == b'\n'
+}
+
+
+/// Guess the encoding of the given bytes. This is a heuristic:
+/// UTF-16 is only detected for ASCII range characters, which is what is usually found in
+/// binaries.
+pub fn detect(bytes: &[u8]) -> Encoding {
+  let is_utf16 = |text_ix: usize| {
+    bytes.len() >= 2
+      && bytes.len().is_multiple_of(2)
+      && bytes.chunks(2).all(|c| c[1 - text_ix] == 0 && is_text(c[text_ix]))
+  };
+
+  if bytes.iter().all(|&b| is_text(b)) {
+    Encoding::Ascii
+  }
+  else if is_utf16(0) {
+    Encoding::Utf16Le
+  }
+  else if is_utf16(1) {
+    Encoding::Utf16Be
+  }
+  else if std::str::from_utf8(bytes).is_ok() {
+    Encoding::Utf8
+  }
+  else {
+    Encoding::Binary
+  }
+}
+
+
+/// The longest text, in characters, to extend a UTF-16 preview on either side of a match.
+const MAX_PREVIEW_UNITS: usize = 64;
+
+
+/// Decode the UTF-16 text surrounding the given range of the buffer, if the range lies in
+/// UTF-16 text. As in `detect`, only ASCII range characters are considered text, so the
+/// text is recognized by its null bytes. The text is extended from the range up to
+/// `MAX_PREVIEW_UNITS` characters on either side.
+pub fn utf16_text(buffer: &[u8], range: Range<usize>) -> Option<(Encoding, String)> {
+  let decode = |text_ix: usize| {
+    // Units are aligned to the start of the range.
+    let is_unit = |position: usize| {
+      buffer.get(position .. position + 2)
+            .is_some_and(|unit| unit[1 - text_ix] == 0 && is_text(unit[text_ix]))
+    };
+
+    let mut start = range.start;
+    while start >= 2 && is_unit(start - 2) && range.start - start < MAX_PREVIEW_UNITS * 2 {
+      start -= 2;
+    }
+
+    let mut end = range.start;
+    while is_unit(end) && (end < range.end || end - range.end < MAX_PREVIEW_UNITS * 2) {
+      end += 2;
+    }
+
+    if end < range.end || end == start {
+      return None;
+    }
+
+    Some(buffer[start .. end].chunks(2).map(|unit| unit[text_ix] as char).collect())
+  };
+
+  match (decode(0), decode(1)) {
+    (Some(text), _) => Some((Encoding::Utf16Le, text)),
+    (_, Some(text)) => Some((Encoding::Utf16Be, text)),
+    _ => None
+  }
+}
+
+
+/// Translate the text to a regex that matches it literally as UTF-16, in little or big
+/// endian, as specified by `--utf16le` and `--utf16be`.
+pub fn utf16_regex(text: &str, big_endian: bool) -> String {
+  text.encode_utf16()
+      .flat_map(|unit| if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() })
+      .map(|b| format!("\\x{:02x}", b))
+      .collect()
+}
@@ -0,0 +1,91 @@
+/// A piece of an output template.
+#[derive(Debug)]
+pub enum Segment {
+  Literal(Vec<u8>),
+  Path,      // %p
+  HexOffset, // %o
+  DecOffset, // %d
+  HexBytes,  // %h
+  RawBytes,  // %b
+  Length,    // %l
+  Group(usize) // %0 to %9, the bytes of the capture group.
+}
+
+
+/// An output template, as specified by `--format`.
+/// The template is expanded once per match.
+#[derive(Debug)]
+pub struct Template {
+  pub segments: Box<[Segment]>
+}
+
+
+impl Template {
+  /// Whether the template references capture groups, requiring captures to be computed.
+  pub fn has_groups(&self) -> bool {
+    self.segments.iter().any(|s| matches!(s, Segment::Group(_)))
+  }
+}
+
+
+/// Parse an output template.
+/// Capture groups are referenced with a single digit, where `%0` is the whole match.
+/// Groups that didn't participate in the match, or that don't exist in the pattern, are
+/// expanded to nothing.
+/// Besides the `%` specifiers, the escapes `\n`, `\t`, `\r`, `\0` and `\\` are supported,
+/// as well as `%%` for a literal percent sign.
+/// Returns the error message if the template contains an invalid specifier or escape.
+pub fn parse<T: AsRef<str>>(template: T) -> Result<Template, String> {
+  let mut segments = Vec::new();
+  let mut literal = Vec::new();
+
+  let mut chars = template.as_ref().chars();
+
+  while let Some(c) = chars.next() {
+    match c {
+      '%' => {
+        let segment = match chars.next() {
+          Some('p') => Segment::Path,
+          Some('o') => Segment::HexOffset,
+          Some('d') => Segment::DecOffset,
+          Some('h') => Segment::HexBytes,
+          Some('b') => Segment::RawBytes,
+          Some('l') => Segment::Length,
+          Some('%') => { literal.push(b'%'); continue }
+          Some(c @ '0' ..= '9') => Segment::Group(c as usize - '0' as usize),
+          Some(c) => return Err(format!("invalid format specifier '%{}'", c)),
+          None => return Err("incomplete format specifier at end of template".to_owned())
+        };
+
+        if !literal.is_empty() {
+          segments.push(Segment::Literal(literal.split_off(0)));
+        }
+
+        segments.push(segment);
+      }
+
+      '\\' => literal.push(
+        match chars.next() {
+          Some('n')  => b'\n',
+          Some('t')  => b'\t',
+          Some('r')  => b'\r',
+          Some('0')  => b'\0',
+          Some('\\') => b'\\',
+          Some(c) => return Err(format!("invalid escape sequence '\\{}'", c)),
+          None => return Err("incomplete escape sequence at end of template".to_owned())
+        }
+      ),
+
+      c => {
+        let mut bytes = [0; 4];
+        literal.extend_from_slice(c.encode_utf8(&mut bytes).as_bytes());
+      }
+    }
+  }
+
+  if !literal.is_empty() {
+    segments.push(Segment::Literal(literal));
+  }
+
+  Ok(Template { segments: segments.into_boxed_slice() })
+}
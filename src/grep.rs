@@ -1,16 +1,103 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::ffi::OsStr;
 use std::io;
-use std::io::{Read, Write};
-use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use std::fmt::Display;
+use std::time::Instant;
+use std::iter::Peekable;
+use std::ops::Range;
 
-use regex::bytes::{Regex, RegexBuilder};
+use regex::bytes::{CaptureLocations, Match, Regex, RegexBuilder};
 
+use crate::alternation::Alternation;
 use crate::args::{self, Args};
+use crate::encoding;
+use crate::format::{Segment, Template};
+use crate::hex;
+use crate::manifest;
+use crate::mtime;
+use crate::pe;
+use crate::prefilter::{self, Candidates};
+use crate::progress::{self, Progress};
+use crate::record;
+use crate::resume::{self, Checkpoint};
+
+
+/// The compiled patterns.
+struct Patterns {
+  regex: Alternation, // All the patterns, each compiled on its own.
+  within: Option<(Regex, Regex)>, // The delimiters of the regions to search, if any.
+  anchor: Option<Regex>, // The anchor that offsets are relative to, if any.
+  not_followed_by: Option<Anchored>, // The pattern that must not match right after a match.
+  probes: Option<Box<[Anchored]>> // The patterns tried at each offset of `--probe-offsets`.
+}
+
+
+/// A pattern that is only tried at a given position, without searching the rest of the
+/// buffer. The byte before the position is still seen by the pattern, so that assertions
+/// such as `\b` hold as they would in a search of the whole buffer.
+struct Anchored {
+  at_start: Regex, // The pattern anchored to the start of the buffer.
+  after_byte: Regex // The pattern anchored right after any byte, the one before the position.
+}
+
+
+impl Anchored {
+  /// Whether the pattern matches at the given position of the buffer.
+  fn is_match_at(&self, buffer: &[u8], position: usize) -> bool {
+    match position.checked_sub(1) {
+      None => self.at_start.is_match(buffer),
+      Some(before) => self.after_byte.is_match(&buffer[before ..])
+    }
+  }
+}
+
+
+/// The error when no file matched and `--require-match` was given, distinguished in the
+/// exit status.
+#[derive(Debug)]
+pub struct NoMatch;
+
+
+impl std::fmt::Display for NoMatch {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "no match found")
+  }
+}
+
+
+impl std::error::Error for NoMatch { }
+
+
+/// The state accumulated across files.
+#[derive(Default)]
+struct State {
+  histogram: BTreeMap<usize, u64>, // The count of matches per offset.
+  value_histogram: BTreeMap<u8, u64>, // The count of matched bytes per value.
+  files: usize, // The number of files that were searched.
+  matched_files: usize, // The number of files with at least one match.
+  errors: usize, // The number of files that failed.
+  bytes: u64, // The number of bytes read.
+  input_len: usize, // The size of the current input, or 0 if unknown.
+  matches: usize, // The total number of matches.
+  json_elements: usize, // The number of elements written to the JSON array.
+  r2_flags: usize, // The number of radare2 flags created, which numbers the next one.
+  offsets: usize, // The number of offsets written, to separate them.
+  label: String, // The label of the current input, kept only for `--top`.
+  top: Vec<(String, usize)>, // The labels and match counts of the matched files.
+  checkpoint: Checkpoint // The position of the scan, saved with `--resume-from`.
+}
 
 
 /// Build the regex pattern with the given options.
 /// By default, the `unicode` flag is set to false, and `dot_matches_new_line` set to true.
+/// With `--dfa-limit`, the one limit bounds both the compiled program and the DFA cache.
+/// Only the former can be reported, as the regex crate silently falls back to slower
+/// engines when the cache is exhausted.
 fn build_pattern<P: AsRef<str>>(
   pattern: &P,
   options: &args::Options
@@ -18,36 +105,248 @@ fn build_pattern<P: AsRef<str>>(
   let mut builder = RegexBuilder::new(pattern.as_ref());
 
   builder.unicode(false);
-  builder.dot_matches_new_line(true);
+  builder.dot_matches_new_line(!options.literal_newline);
   builder.case_insensitive(options.case_insensitive);
 
+  if let Some(limit) = options.dfa_limit {
+    builder.dfa_size_limit(limit);
+    builder.size_limit(limit);
+  }
+
   builder.build()
 }
 
 
-/// Run bgrep, outputting `path` to the given `out` if there is a match.
-/// Returns whether there was a match.
-fn grep_filename<O: Write, P: Display, B: AsRef<[u8]>>(
-  out: &mut O,
-  options: &args::Options,
-  pattern: &Regex,
+/// Iterate the matches of `patterns` in `buffer`, with the index of the pattern that
+/// produced each, reporting progress to stderr if enabled.
+/// Only matches starting with the byte of `--prefilter` are considered, matches that fail
+/// the constraints of `--word-bytes` and `--backref` are dropped, and the iteration stops
+/// when `--max-matches-total` is reached.
+fn find_iter<'o, 'r, 't, P: Display>(
+  options: &'o args::Options,
+  patterns: &'r Patterns,
   path: P,
-  buffer: B
-) -> io::Result<bool> {
-  let buffer = buffer.as_ref();
+  buffer: &'t [u8]
+) -> Progress<Limited<'o, Constrained<'o, 'r, 't, Candidates<'r, 't>>>> {
+  let matches = prefilter::find_iter(&patterns.regex, options.prefilter, buffer);
+  let matches = Constrained { matches, options, patterns, buffer };
+  let matches = Limited { matches, remaining: options.remaining_matches.as_ref() };
+
+  progress::track(matches, options.progress, path, options.progress_window.get(), buffer.len())
+}
+
+
+/// An iterator adapter over matches or offsets that stops when the remaining matches are
+/// exhausted, if limited.
+/// The remaining count is shared across all files.
+struct Limited<'o, I> {
+  matches: I,
+  remaining: Option<&'o Cell<usize>>
+}
+
+
+impl<'o, I: Iterator> Iterator for Limited<'o, I> {
+  type Item = I::Item;
+
+  fn next(&mut self) -> Option<I::Item> {
+    match self.remaining {
+      None => self.matches.next(),
+      Some(remaining) if remaining.get() == 0 => None,
+      Some(remaining) => {
+        let m = self.matches.next()?;
+        remaining.set(remaining.get() - 1);
+        Some(m)
+      }
+    }
+  }
+}
+
+
+/// Whether the match is delimited by non word bytes or by the bounds of the buffer, with
+/// the word bytes defined by `--word-bytes`. Always true without it.
+fn word_bounded(options: &args::Options, buffer: &[u8], m: &Match) -> bool {
+  match options.word_bytes {
+    None => true,
+    Some(ref word_bytes) => {
+      let is_word = |ix: Option<usize>| ix.and_then(|ix| buffer.get(ix))
+                                          .is_some_and(|&b| word_bytes[b as usize]);
+
+      !is_word(m.start().checked_sub(1)) && !is_word(Some(m.end()))
+    }
+  }
+}
+
+
+/// Whether the match satisfies the byte equalities given with `--backref`. Positions are
+/// relative to the start of the match, and those beyond its end fail the equality.
+fn backrefs_hold(options: &args::Options, m: &Match) -> bool {
+  let bytes = m.as_bytes();
+
+  options.backrefs.iter().all(
+    |&(a, b)| match (bytes.get(a), bytes.get(b)) {
+      (Some(x), Some(y)) => x == y,
+      _ => false
+    }
+  )
+}
+
+
+/// Whether the match is not immediately followed by a match of `--not-followed-by`. Always
+/// true without it.
+fn not_followed(patterns: &Patterns, buffer: &[u8], m: &Match) -> bool {
+  match patterns.not_followed_by {
+    None => true,
+    Some(ref pattern) => !pattern.is_match_at(buffer, m.end())
+  }
+}
+
+
+/// Whether the match satisfies all the constraints that are checked after matching.
+/// As the regex engine has no lookaround nor backreferences, a rejected match hides any
+/// overlapping match that would satisfy the constraints.
+fn is_constrained(options: &args::Options, patterns: &Patterns, buffer: &[u8], m: &Match) -> bool {
+  word_bounded(options, buffer, m) && backrefs_hold(options, m) && not_followed(patterns, buffer, m)
+}
+
+
+/// An iterator adapter that drops the matches that fail the constraints.
+struct Constrained<'o, 'r, 't, I> {
+  matches: I,
+  options: &'o args::Options,
+  patterns: &'r Patterns,
+  buffer: &'t [u8]
+}
+
+
+impl<'o, 'r, 't, I> Iterator for Constrained<'o, 'r, 't, I>
+where I: Iterator<Item = (usize, Match<'t>)> {
+  type Item = (usize, Match<'t>);
+
+  fn next(&mut self) -> Option<(usize, Match<'t>)> {
+    let (options, patterns, buffer) = (self.options, self.patterns, self.buffer);
+    self.matches.find(|(_, m)| is_constrained(options, patterns, buffer, m))
+  }
+}
 
+
+/// An iterator adapter that coalesces adjacent matches, i.e. when a match ends where the
+/// next one starts, yielding the combined ranges.
+struct MergeAdjacent<I: Iterator> {
+  matches: Peekable<I>
+}
+
+
+impl<'t, I: Iterator<Item = (usize, Match<'t>)>> Iterator for MergeAdjacent<I> {
+  type Item = Range<usize>;
+
+  fn next(&mut self) -> Option<Range<usize>> {
+    let (_, first) = self.matches.next()?;
+    let mut range = first.start() .. first.end();
+
+    while let Some((_, m)) = self.matches.peek() {
+      if m.start() != range.end {
+        break;
+      }
+
+      range.end = m.end();
+      self.matches.next();
+    }
+
+    Some(range)
+  }
+}
+
+
+fn merge_adjacent<I: Iterator>(matches: I) -> MergeAdjacent<I> {
+  MergeAdjacent { matches: matches.peekable() }
+}
+
+
+/// A match extended with the surrounding context bytes. Matches whose contexts overlap
+/// share a single window.
+struct Window {
+  // The bytes to output, clamped to the buffer bounds. As records and `--within` regions
+  // are searched as buffers of their own, context never crosses their delimiters.
+  range: Range<usize>,
+  index: usize,  // The index of the pattern that produced the first match in the window.
+  matches: usize // The number of matches in the window.
+}
+
+
+/// An iterator adapter that extends matches with `before` and `after` context bytes,
+/// merging overlapping windows.
+struct Context<I: Iterator> {
+  matches: Peekable<I>,
+  before: usize,
+  after: usize,
+  len: usize // The length of the buffer.
+}
+
+
+impl<'t, I: Iterator<Item = (usize, Match<'t>)>> Iterator for Context<I> {
+  type Item = Window;
+
+  fn next(&mut self) -> Option<Window> {
+    let (index, first) = self.matches.next()?;
+
+    let start = first.start().saturating_sub(self.before);
+    let mut end = first.end().saturating_add(self.after).min(self.len);
+    let mut matches = 1;
+
+    while let Some((_, m)) = self.matches.peek() {
+      if m.start().saturating_sub(self.before) > end {
+        break;
+      }
+
+      end = end.max(m.end().saturating_add(self.after).min(self.len));
+      matches += 1;
+      self.matches.next();
+    }
+
+    Some(Window { range: start .. end, index, matches })
+  }
+}
+
+
+fn context<I: Iterator>(matches: I, options: &args::Options, len: usize) -> Context<I> {
+  Context {
+    matches: matches.peekable(),
+    before: options.before_context,
+    after: options.after_context,
+    len
+  }
+}
+
+
+/// Flush `out` after a match has been written, if requested with `--line-buffered`.
+fn flush_match<O: Write>(out: &mut O, options: &args::Options) -> io::Result<()> {
+  if options.line_buffered {
+    out.flush()
+  } else {
+    Ok(())
+  }
+}
+
+
+/// Check whether there is a match in the buffer, considering inverse matching.
+fn is_match<P: Display>(
+  options: &args::Options,
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8]
+) -> bool {
   // When inverse matching, matches must be checked until a "hole" is found.
   // Otherwise, the more performant `Regex::is_match` can be used.
   if options.inverse {
     // if the pattern matches multiple times, comprising the entire buffer, then no
     // inverse match is present.
-    let mut matches = pattern.find_iter(buffer);
+    let mut matches = find_iter(options, patterns, path, buffer);
 
     let mut end = 0; // Start from the beginning of the buffer.
 
     // Try to find a "hole" between matches:
     let inverse_match = matches.find(
-      |m| {
+      |(_, m)| {
         let matched = m.start() > end;
 
         end = m.end();
@@ -57,228 +356,1712 @@ fn grep_filename<O: Write, P: Display, B: AsRef<[u8]>>(
     );
 
     // Also check for a "hole" after the last match.
-    let matched = (inverse_match.is_some() || end < buffer.len())
-                ^ options.non_matching; // List non matching files.
+    inverse_match.is_some() || end < buffer.len()
+  }
+  else if options.word_bytes.is_some() || !options.backrefs.is_empty()
+       || options.not_followed_by.is_some() || options.prefilter.is_some() || options.progress {
+    find_iter(options, patterns, path, buffer).next().is_some()
+  }
+  else {
+    patterns.regex.is_match(buffer)
+  }
+}
 
-    if matched {
-      writeln!(out, "{}", path)?;
-    }
 
-    Ok(matched)
+/// Run bgrep, outputting `path` to the given `out` if there is a match, or if there isn't
+/// when listing non matching files.
+/// Returns the number of matches, which is 1 if `path` was outputted.
+fn grep_filename<O: Write, P: Display, B: AsRef<[u8]>>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  path: P,
+  buffer: B
+) -> io::Result<usize> {
+  let buffer = buffer.as_ref();
+
+  // Listing non matching files is about the absence of the pattern, which is unrelated to
+  // the inverse regions of (-v).
+  let mut count = None; // The number of matches, if already counted.
+
+  let listed =
+    if options.non_matching {
+      !patterns.regex.find_iter(buffer).any(|(_, m)| is_constrained(options, patterns, buffer, &m))
+    } else if let Some(min_count) = options.min_count {
+      let matches = count_matches(options, patterns, &path, buffer)[0];
+      count = Some(matches);
+      matches >= min_count
+    } else {
+      is_match(options, patterns, &path, buffer)
+    };
+
+  if options.with_count && listed {
+    let count = count.unwrap_or_else(|| count_matches(options, patterns, &path, buffer)[0]);
+
+    writeln!(out, "{}: {}", path, count)?;
+    flush_match(out, options)?;
+
+    // Non matching files are listed with a zero count, but still count as selected.
+    return Ok(if options.non_matching { 1 } else { count });
+  }
+
+  write_filename(out, options, path, listed)
+}
+
+
+/// Output `path` to the given `out` if it is listed.
+/// Returns the number of matches, which is 1 if `path` was outputted.
+fn write_filename<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  path: P,
+  listed: bool
+) -> io::Result<usize> {
+  if listed {
+    writeln!(out, "{}", path)?;
+    flush_match(out, options)?;
+  }
+
+  Ok(listed as usize)
+}
+
+
+/// Run bgrep, outputting `path` to the given `out` followed by whether there was a match.
+/// Returns the number of matches, which is 1 if the file matched.
+fn grep_status<O: Write, P: Display, B: AsRef<[u8]>>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  path: P,
+  buffer: B
+) -> io::Result<usize> {
+  let matched = is_match(options, patterns, &path, buffer.as_ref());
+
+  write_status(out, options, path, matched)
+}
+
+
+/// Output `path` to the given `out` followed by whether there was a match.
+/// Returns the number of matches, which is 1 if the file matched.
+fn write_status<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  path: P,
+  matched: bool
+) -> io::Result<usize> {
+  writeln!(out, "{}: {}", path, if matched { "MATCH" } else { "NO-MATCH" })?;
+  flush_match(out, options)?;
+
+  Ok(matched as usize)
+}
+
+
+/// Count the matches in the buffer, considering inverse matching. When breaking down the
+/// count, each pattern is counted on its own, and the result has one entry per pattern.
+/// Otherwise, the result has a single entry.
+/// No matched bytes are materialized, as a `Match` only borrows the buffer, so the only
+/// allocation is the result.
+fn count_matches<P: Display>(
+  options: &args::Options,
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8]
+) -> Vec<usize> {
+  if options.count_breakdown {
+    let constrained = |m: &Match| is_constrained(options, patterns, buffer, m);
+
+    patterns.regex.patterns()
+                  .iter()
+                  .map(|p| p.find_iter(buffer).filter(constrained).count())
+                  .collect()
+  }
+  else if options.inverse {
+    vec![patterns.regex.split(buffer).filter(|bs| !bs.is_empty()).count()]
   }
   else {
-    let matched = pattern.is_match(buffer) ^ options.non_matching;
+    vec![find_iter(options, patterns, path, buffer).count()]
+  }
+}
 
-    if matched {
-      writeln!(out, "{}", path)?;
-    }
 
-    Ok(matched)
+/// Run bgrep, outputting the number of matches to the given `out`.
+/// Returns the number of matches.
+fn grep_count<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8]
+) -> io::Result<usize> {
+  let counts = count_matches(options, patterns, &path, buffer);
+
+  write_count(out, options, path, &counts)
+}
+
+
+/// Output the number of matches to the given `out`, labeled by pattern if there are
+/// multiple counts.
+/// Returns the total number of matches.
+fn write_count<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  path: P,
+  counts: &[usize]
+) -> io::Result<usize> {
+  if options.print_filename {
+    write!(out, "{}: ", path)?;
+  }
+
+  match counts {
+    [count] if !options.count_breakdown => write!(out, "{}", count)?,
+    counts => for (ix, count) in counts.iter().enumerate() {
+      write!(out, "{}p{}={}", if ix == 0 { "" } else { " " }, ix, count)?;
+    }
   }
+
+  writeln!(out)?;
+  flush_match(out, options)?;
+
+  Ok(counts.iter().sum())
 }
 
 
 /// Run bgrep, outputting the matched bytes to the given `out`.
-/// Returns whether there was a match.
+/// Returns the number of matches.
 fn grep_bytes<O: Write, P: Display, B: AsRef<[u8]>>(
   out: &mut O,
   options: &args::Options,
-  pattern: &Regex,
+  patterns: &Patterns,
   path: P,
   buffer: B,
-) -> io::Result<bool> {
+) -> io::Result<usize> {
   let buffer = buffer.as_ref();
 
-  let mut write_bytes = |bs| {
-    if options.print_filename {
+  let mut write_bytes = |bs, index: Option<usize>| {
+    // With `--concat`, only the bytes are written, back to back.
+    if options.print_filename && !options.concat {
       write!(out, "{}: ", path)?;
     }
 
-    out.write_all(bs)?;
-    writeln!(out)
+    if let Some(index) = index {
+      write!(out, "[p{}] ", index)?;
+    }
+
+    match options.hash {
+      Some(algorithm) => for b in algorithm.digest(bs) { write!(out, "{:02x}", b)? },
+      None => {
+        out.write_all(bs)?;
+
+        // Pad short matches to the width given by `--pad-to`.
+        for _ in bs.len() .. options.pad_to {
+          out.write_all(&[options.pad_byte])?;
+        }
+      }
+    }
+
+    if !options.concat {
+      writeln!(out)?;
+    }
+
+    flush_match(out, options)
   };
 
+  // The pattern index is only output when requested.
+  let index = |ix| if options.show_pattern { Some(ix) } else { None };
 
-  let mut matched = false;
+
+  let mut count = 0;
 
   if options.inverse {
     // `Regex::split` yields the slices outside the matches.
-    let mut matches = pattern.split(buffer);
-
-    // Set `matched` if there is a first occurrence:
-    if let Some(bs) = matches.next() {
-      if !bs.is_empty() { // A regex may have a empty match, but when inverse matching
-        write_bytes(bs)?; // we disconsider empty intervals.
-        matched = true;
-      }
-    };
-
-    // Iterate the remaining matches:
-    for bs in matches {
-      if !bs.is_empty() {
-        write_bytes(bs)?;
+    for bs in patterns.regex.split(buffer) {
+      if !bs.is_empty() {       // A regex may have a empty match, but when inverse
+        write_bytes(bs, None)?; // matching we disconsider empty intervals.
+        count += 1;
       }
     }
   }
-  else {
-    let mut matches = pattern.find_iter(buffer);
-
-    // Set `matched` if there is a first occurrence:
-    if let Some(m) = matches.next() {
-      write_bytes(m.as_bytes())?;
-      matched = true;
+  else if options.merge_adjacent {
+    for range in merge_adjacent(find_iter(options, patterns, &path, buffer)) {
+      write_bytes(&buffer[range], None)?;
+      count += 1;
     }
-
-    // Iterate the remaining matches:
-    for m in matches {
-      write_bytes(m.as_bytes())?;
+  }
+  else if options.before_context > 0 || options.after_context > 0 {
+    for window in context(find_iter(options, patterns, &path, buffer), options, buffer.len()) {
+      write_bytes(&buffer[window.range], index(window.index))?;
+      count += window.matches;
+    }
+  }
+  else {
+    for (ix, m) in find_iter(options, patterns, &path, buffer) {
+      write_bytes(m.as_bytes(), index(ix))?;
+      count += 1;
     }
   };
 
 
-  Ok(matched)
+  Ok(count)
 }
 
 
 /// Run bgrep, outputting the matche's offset in hex to the given `out`.
-/// Returns whether there was a match.
+/// Returns the number of matches.
 fn grep_offset<O: Write, P: Display, B: AsRef<[u8]>>(
   out: &mut O,
   options: &args::Options,
-  pattern: &Regex,
+  patterns: &Patterns,
+  state: &mut State,
   path: P,
-  buffer: B
-) -> io::Result<bool> {
+  buffer: B,
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
   let buffer = buffer.as_ref();
 
-  let mut write_hex = |x| {
+  // The number of hex digits to zero pad offsets to.
+  let width = match options.offset_width {
+    Some(args::OffsetWidth::Fixed(width)) => width,
+    Some(args::OffsetWidth::Auto) => format!("{:x}", state.input_len).len(),
+    None => 0
+  };
+
+  let offsets = &mut state.offsets; // The number of offsets written across all files.
+  let input_len = state.input_len;
+
+  // The start offsets of the anchors, in order.
+  let anchors: Option<Vec<usize>> = patterns.anchor.as_ref().map(
+    |anchor| anchor.find_iter(buffer).map(|m| m.start()).collect()
+  );
+
+  let mut write_hex = |range: Range<usize>, index: Option<usize>| {
+    // With `--offset-separator`, offsets are separated rather than terminated, and the
+    // line is ended by `run`.
+    if let (Some(ref separator), true) = (&options.offset_separator, *offsets > 0) {
+      out.write_all(separator.as_bytes())?;
+    }
+    *offsets += 1;
+
     if options.print_filename {
-      writeln!(out, "{}: 0x{:x}", path, x)
+      write!(out, "{}: ", path)?;
+    }
+
+    if let Some(index) = index {
+      write!(out, "[p{}] ", index)?;
+    }
+
+    if let Some(ref anchors) = anchors {
+      // The caller skips matches before the first anchor.
+      let ix = anchors.partition_point(|&anchor| anchor <= range.start) - 1;
+      write!(out, "#{}+0x{:0width$x}", ix, range.start - anchors[ix], width = width)?;
+    } else if options.offset_from_end { // Offsets are never relative to a PE section or record.
+      write!(out, "-0x{:0width$x}", input_len - (base + range.start), width = width)?;
     } else {
-      writeln!(out, "0x{:x}", x)
+      write!(out, "0x{:0width$x}", base + range.start, width = width)?;
+    }
+
+    if options.merge_adjacent || options.gaps { // Both are reported as ranges.
+      write!(out, "-0x{:0width$x}", base + range.end, width = width)?;
+    }
+
+    if options.with_length {
+      let length = range.end - range.start;
+      write!(out, " ({} byte{})", length, if length == 1 { "" } else { "s" })?;
+    }
+
+    if options.detect_encoding {
+      write!(out, " ({})", encoding::detect(&buffer[range.clone()]).name())?;
+    }
+
+    if options.utf16_preview {
+      if let Some((encoding, text)) = encoding::utf16_text(buffer, range.clone()) {
+        write!(out, " ({} {:?})", encoding.name(), text)?;
+      }
+    }
+
+    if options.offset_preview > 0 {
+      let bytes = &buffer[range];
+      let preview = &bytes[.. bytes.len().min(options.offset_preview)];
+
+      write!(out, ":")?;
+      for b in preview {
+        write!(out, " {:02x}", b)?;
+      }
+    }
+
+    if options.offset_separator.is_none() {
+      writeln!(out)?;
     }
+    flush_match(out, options)
   };
 
+  // The pattern index is only output when requested.
+  let index = |ix| if options.show_pattern { Some(ix) } else { None };
 
-  let mut matches = pattern.find_iter(buffer);
+  // Matches before the first anchor have no position relative to it.
+  let first_anchor = anchors.as_ref().map(|anchors| anchors.first().copied());
 
-  let mut matched = false;
 
-  if options.inverse {
+  let matches = find_iter(options, patterns, &path, buffer);
+
+  let mut count = 0;
+
+  if options.inverse || options.gaps {
     // if the pattern matches multiple times, comprising the entire buffer, then no
     // inverse match is present.
     let mut end = 0; // Start from the beginning of the buffer.
 
-    for m in matches {
+    for (_, m) in matches {
       if m.start() > end {
-        write_hex(end)?;
-        matched = true;
+        write_hex(end .. m.start(), None)?;
+        count += 1;
       }
 
       end = m.end()
     }
 
     if end < buffer.len() { // Also check for a "hole" after the last match.
-      write_hex(end)?;
-      matched = true;
+      write_hex(end .. buffer.len(), None)?;
+      count += 1;
+    }
+  }
+  else if options.merge_adjacent {
+    for range in merge_adjacent(matches) {
+      write_hex(range, None)?;
+      count += 1;
+    }
+  }
+  else if options.span {
+    let mut span: Option<Range<usize>> = None;
+
+    for (_, m) in matches {
+      span = Some(match span {
+        Some(span) => span.start.min(m.start()) .. span.end.max(m.end()),
+        None => m.start() .. m.end()
+      });
+      count += 1;
+    }
+
+    if let Some(span) = span {
+      if options.print_filename {
+        write!(out, "{}: ", path)?;
+      }
+
+      writeln!(
+        out,
+        "0x{:0width$x} - 0x{:0width$x}",
+        base + span.start,
+        base + span.end,
+        width = width
+      )?;
+      flush_match(out, options)?;
+    }
+  }
+  else if let Some(group) = options.offset_group {
+    for (ix, m) in matches {
+      // Captures are slower than plain matches, so they are only resolved for the matches
+      // found. Groups are numbered within the pattern that produced the match.
+      let g = match patterns.regex.captures_at(ix, buffer, m.start()).get(group) {
+        Some(g) => g,
+        None => continue
+      };
+
+      if first_anchor.is_some_and(|first| first.is_none_or(|first| g.0 < first)) {
+        continue;
+      }
+
+      write_hex(g.0 .. g.1, index(ix))?;
+      count += 1;
     }
   }
   else {
-    // Set `matched` if there is a first occurrence:
-    if let Some(m) = matches.next() {
-      write_hex(m.start())?;
-      matched = true;
+    let mut last = None; // With `--last`, only the last match is written, once all are found.
+
+    for (ix, m) in matches {
+      if first_anchor.is_some_and(|first| first.is_none_or(|first| m.start() < first)) {
+        continue;
+      }
+
+      if options.last {
+        last = Some((ix, m));
+      } else {
+        write_hex(m.start() .. m.end(), index(ix))?;
+      }
+
+      count += 1;
     }
 
-    // Iterate the remaining matches:
-    for m in matches {
-      write_hex(m.start())?;
+    if let Some((ix, m)) = last {
+      write_hex(m.start() .. m.end(), index(ix))?;
     }
   }
 
 
-  Ok(matched)
+  Ok(count)
 }
 
 
-/// Run bgrep with the given options, outputting to the given `out`.
-/// Error detail may be outputted to stderr.
-/// Returns whether there was a match.
-fn run_file<O: Write, P: AsRef<Path>, B: AsMut<Vec<u8>>>(
+/// Run bgrep, outputting each match expanded with the given template to `out`.
+/// Returns the number of matches.
+fn grep_format<O: Write, P: Display, B: AsRef<[u8]>>(
   out: &mut O,
   options: &args::Options,
-  pattern: &Regex,
+  template: &Template,
+  patterns: &Patterns,
   path: P,
-  buffer: &mut B
-) -> io::Result<bool> {
-  let buffer = buffer.as_mut();
-  let path = path.as_ref();
+  buffer: B,
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let buffer = buffer.as_ref();
+
+  let mut write_match = |start: usize, end: usize, captures: Option<&CaptureLocations>| {
+    let bytes = &buffer[start .. end];
+
+    for segment in template.segments.iter() {
+      match segment {
+        Segment::Literal(bs) => out.write_all(bs)?,
+        Segment::Path        => write!(out, "{}", path)?,
+        Segment::HexOffset   => write!(out, "0x{:x}", base + start)?,
+        Segment::DecOffset   => write!(out, "{}", base + start)?,
+        Segment::HexBytes    => for b in bytes { write!(out, "{:02x}", b)? },
+        Segment::RawBytes    => out.write_all(bytes)?,
+        Segment::Length      => write!(out, "{}", bytes.len())?,
+        Segment::Group(ix)   =>
+          if let Some((start, end)) = captures.and_then(|c| c.get(*ix)) {
+            out.write_all(&buffer[start .. end])?
+          }
+      }
+    }
+
+    flush_match(out, options)
+  };
+
+
+  let mut count = 0;
+
+  if options.inverse {
+    // Expand the template for every "hole" between matches, like in `grep_offset`.
+    let mut end = 0; // Start from the beginning of the buffer.
+
+    for (_, m) in find_iter(options, patterns, &path, buffer) {
+      if m.start() > end {
+        write_match(end, m.start(), None)?;
+        count += 1;
+      }
+
+      end = m.end()
+    }
+
+    if end < buffer.len() { // Also check for a "hole" after the last match.
+      write_match(end, buffer.len(), None)?;
+      count += 1;
+    }
+  }
+  else if template.has_groups() {
+    // Captures are slower than plain matches, so they are only resolved when required.
+    for (ix, m) in find_iter(options, patterns, &path, buffer) {
+      let captures = patterns.regex.captures_at(ix, buffer, m.start());
+
+      write_match(m.start(), m.end(), Some(&captures))?;
+      count += 1;
+    }
+  }
+  else {
+    for (_, m) in find_iter(options, patterns, &path, buffer) {
+      write_match(m.start(), m.end(), None)?;
+      count += 1;
+    }
+  }
+
+
+  Ok(count)
+}
+
+
+/// Run bgrep, outputting the buffer with every match replaced by `replacement` to the
+/// given `out`.
+/// Returns the number of matches.
+fn grep_replace<O: Write, B: AsRef<[u8]>>(
+  out: &mut O,
+  options: &args::Options,
+  replacement: &[u8],
+  patterns: &Patterns,
+  buffer: B
+) -> io::Result<usize> {
+  let buffer = buffer.as_ref();
+
+  let mut count = 0;
+  let mut end = 0; // The end of the last match.
+
+  // The replacement is a raw blob, therefore capture references are not expanded.
+  let matches = patterns.regex.find_iter(buffer);
+
+  for (_, m) in matches.filter(|(_, m)| is_constrained(options, patterns, buffer, m)) {
+    out.write_all(&buffer[end .. m.start()])?;
+    out.write_all(replacement)?;
+
+    end = m.end();
+    count += 1;
+  }
+
+  out.write_all(&buffer[end ..])?;
+  flush_match(out, options)?;
+
+  Ok(count)
+}
+
+
+/// Run bgrep with a masked or literal signature, outputting the offset of every occurrence
+/// in hex to the given `out`.
+/// Returns the number of matches.
+fn grep_signature<O: Write, P: Display, I: Iterator<Item = usize>>(
+  out: &mut O,
+  options: &args::Options,
+  offsets: I,
+  path: P,
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let mut count = 0;
+
+  let offsets = Limited { matches: offsets, remaining: options.remaining_matches.as_ref() };
+
+  for offset in offsets {
+    if options.print_filename {
+      write!(out, "{}: ", path)?;
+    }
+
+    writeln!(out, "0x{:x}", base + offset)?;
+    flush_match(out, options)?;
+
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+
+/// Run bgrep, counting the matches per offset in the given histogram.
+/// Returns the number of matches.
+fn grep_histogram<P: Display>(
+  options: &args::Options,
+  patterns: &Patterns,
+  histogram: &mut BTreeMap<usize, u64>,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> usize {
+  let mut count = 0;
+
+  for (_, m) in find_iter(options, patterns, path, buffer) {
+    *histogram.entry(base + m.start()).or_insert(0) += 1;
+    count += 1;
+  }
+
+  count
+}
+
+
+/// Run bgrep, counting the matched bytes per value in the given histogram.
+/// Returns the number of matches.
+fn grep_value_histogram<P: Display>(
+  options: &args::Options,
+  patterns: &Patterns,
+  histogram: &mut BTreeMap<u8, u64>,
+  path: P,
+  buffer: &[u8]
+) -> usize {
+  let mut count = 0;
+
+  for (_, m) in find_iter(options, patterns, path, buffer) {
+    for &b in m.as_bytes() {
+      *histogram.entry(b).or_insert(0) += 1;
+    }
+
+    count += 1;
+  }
+
+  count
+}
+
+
+/// Run bgrep, outputting each match as a binary record to the given `out`. The record
+/// format is the absolute offset as u64 little endian, the length as u32 little endian,
+/// and then the matched bytes.
+/// Returns the number of matches.
+fn grep_binary_out<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let mut count = 0;
+
+  for (_, m) in find_iter(options, patterns, &path, buffer) {
+    let len = u32::try_from(m.end() - m.start()).map_err(
+      |_| {
+        eprintln!("Error: match at 0x{:x} in file '{}' is too long", base + m.start(), path);
+        io::Error::from(io::ErrorKind::InvalidData)
+      }
+    )?;
+
+    out.write_all(&((base + m.start()) as u64).to_le_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(m.as_bytes())?;
+    flush_match(out, options)?;
+
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+
+/// Run bgrep, outputting the matched bytes of each match in lowercase hex to the given
+/// `out`, one match per line.
+/// Returns the number of matches.
+fn grep_bytes_hex<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8]
+) -> io::Result<usize> {
+  let mut count = 0;
+
+  for (_, m) in find_iter(options, patterns, &path, buffer) {
+    if options.print_filename {
+      write!(out, "{}: ", path)?;
+    }
+
+    for b in m.as_bytes() {
+      write!(out, "{:02x}", b)?;
+    }
+    writeln!(out)?;
+    flush_match(out, options)?;
+
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+
+/// Run bgrep, outputting the offset of each match and its bytes in lowercase hex to the
+/// given `out`, one match per line. The match is surrounded by up to `context` bytes on
+/// either side, clamped to the buffer bounds, and delimited by `|`.
+/// Returns the number of matches.
+fn grep_context_hex<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  context: usize,
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let mut count = 0;
+
+  let write_hex = |out: &mut O, bytes: &[u8]| {
+    bytes.iter().try_for_each(|b| write!(out, "{:02x}", b))
+  };
+
+  for (_, m) in find_iter(options, patterns, &path, buffer) {
+    if options.print_filename {
+      write!(out, "{}: ", path)?;
+    }
+
+    let start = m.start().saturating_sub(context);
+    let end = m.end().saturating_add(context).min(buffer.len());
+
+    write!(out, "0x{:x}: ", base + m.start())?;
+    write_hex(out, &buffer[start .. m.start()])?;
+    out.write_all(b"|")?;
+    write_hex(out, m.as_bytes())?;
+    out.write_all(b"|")?;
+    write_hex(out, &buffer[m.end() .. end])?;
+    writeln!(out)?;
+    flush_match(out, options)?;
+
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+
+/// Run bgrep, outputting each match as a hexdump to the given `out`: a header line with the
+/// offset of the match, and then rows of `cols` bytes with the absolute offset, the hex bytes
+/// in groups of 8, and the printable ASCII characters. Lines end with `--hex-row-sep`.
+/// Returns the number of matches.
+fn grep_hexdump<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  cols: usize,
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let mut count = 0;
+
+  for (_, m) in find_iter(options, patterns, &path, buffer) {
+    if options.print_filename {
+      write!(out, "{}: ", path)?;
+    }
+
+    write!(out, "0x{:x}:", base + m.start())?;
+    out.write_all(&[options.hex_row_sep])?;
+
+    for (ix, row) in m.as_bytes().chunks(cols).enumerate() {
+      write!(out, "{:08x} ", base + m.start() + ix * cols)?;
+
+      // Short rows are padded, so that the ASCII column stays aligned.
+      for col in 0 .. cols {
+        if col % 8 == 0 {
+          out.write_all(b" ")?;
+        }
+
+        match row.get(col) {
+          Some(b) => write!(out, "{:02x} ", b)?,
+          None => out.write_all(b"   ")?
+        }
+      }
+
+      out.write_all(b" |")?;
+      for &b in row {
+        out.write_all(&[if b.is_ascii_graphic() || b == b' ' { b } else { b'.' }])?;
+      }
+      out.write_all(&[b'|', options.hex_row_sep])?;
+    }
+
+    flush_match(out, options)?;
+
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+
+/// Find the NUL terminated strings in the buffer, excluding the terminators. Empty strings
+/// and a trailing unterminated run are skipped.
+fn cstrings(buffer: &[u8]) -> Vec<Range<usize>> {
+  let mut strings = Vec::new();
+  let mut start = 0; // The start of the current string.
+
+  for (ix, &b) in buffer.iter().enumerate() {
+    if b == 0 {
+      if ix > start {
+        strings.push(start .. ix);
+      }
+
+      start = ix + 1;
+    }
+  }
+
+  strings
+}
+
+
+/// Find the runs of printable ASCII, including spaces and tabs, of at least `min_len`
+/// bytes, as the `strings` tool does.
+fn printable_runs(buffer: &[u8], min_len: usize) -> Vec<Range<usize>> {
+  let mut runs = Vec::new();
+  let mut start = 0; // The start of the current run.
+
+  for ix in 0 ..= buffer.len() {
+    let printable = buffer.get(ix)
+                          .is_some_and(|&b| b.is_ascii_graphic() || b == b' ' || b == b'\t');
+
+    if !printable {
+      if ix - start >= min_len.max(1) {
+        runs.push(start .. ix);
+      }
+
+      start = ix + 1;
+    }
+  }
+
+  runs
+}
+
+
+/// Run bgrep over each of the given strings of the buffer on its own, outputting the offset
+/// and contents of the strings that match to the given `out`.
+/// Returns the number of matching strings.
+fn grep_strings<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  strings: Vec<Range<usize>>,
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let mut count = 0;
+
+  for range in strings {
+    let string = &buffer[range.clone()];
+
+    // Constraints are checked against the string, so that its ends act as boundaries.
+    let mut matches = patterns.regex.find_iter(string);
+
+    if !matches.any(|(_, m)| is_constrained(options, patterns, string, &m)) {
+      continue;
+    }
+
+    if options.print_filename {
+      write!(out, "{}: ", path)?;
+    }
+
+    write!(out, "0x{:x}: ", base + range.start)?;
+    out.write_all(string)?;
+    writeln!(out)?;
+    flush_match(out, options)?;
+
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+
+/// Run bgrep, writing each match to its own file in `dir`, and outputting the path of each
+/// written file to the given `out`. The file names are the path of the input, with the
+/// separators replaced, followed by the offset of the match.
+/// Returns the number of matches.
+fn grep_extract<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  dir: &Path,
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let source: String = path.to_string()
+                           .trim_start_matches(['/', '\\'])
+                           .chars()
+                           .map(|c| if c == '/' || c == '\\' { '_' } else { c })
+                           .collect();
+
+  let mut count = 0;
+
+  for (_, m) in find_iter(options, patterns, &path, buffer) {
+    let file = dir.join(format!("{}_0x{:x}.bin", source, base + m.start()));
+
+    fs::write(&file, m.as_bytes()).inspect_err(
+      |e| eprintln!("Error: failed to write match to '{}', {}", file.display(), e)
+    )?;
+
+    writeln!(out, "{}", file.display())?;
+    flush_match(out, options)?;
+
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+
+/// Run bgrep, outputting whether the pattern matches starting at each of the given offsets
+/// to the given `out`. Offsets are absolute, and those outside of the buffer never match.
+/// Returns the number of matching offsets.
+fn grep_probe<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  offsets: &[usize],
+  patterns: &Patterns,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let mut count = 0;
+
+  for &offset in offsets {
+    // The patterns are only tried at the offset, and searched for the match once one of
+    // them is known to start there.
+    let matched = offset.checked_sub(base)
+                        .filter(|&start| start <= buffer.len())
+                        .filter(
+                          |&start| match patterns.probes {
+                            Some(ref probes) => probes.iter().any(|p| p.is_match_at(buffer, start)),
+                            None => true
+                          }
+                        )
+                        .and_then(|start| patterns.regex.find_at(buffer, start))
+                        .is_some_and(
+                          |(_, m)| {
+                            m.start() + base == offset
+                              && is_constrained(options, patterns, buffer, &m)
+                          }
+                        );
+
+    if options.print_filename {
+      write!(out, "{}: ", path)?;
+    }
+
+    writeln!(out, "0x{:x}: {}", offset, if matched { "MATCH" } else { "NO-MATCH" })?;
+    flush_match(out, options)?;
+
+    count += matched as usize;
+  }
+
+  Ok(count)
+}
+
+
+/// Write the string as a JSON string literal, escaping quotes, backslashes and control
+/// characters.
+fn write_json_string<O: Write>(out: &mut O, string: &str) -> io::Result<()> {
+  out.write_all(b"\"")?;
+
+  for c in string.chars() {
+    match c {
+      '"'  => out.write_all(b"\\\"")?,
+      '\\' => out.write_all(b"\\\\")?,
+      '\n' => out.write_all(b"\\n")?,
+      '\t' => out.write_all(b"\\t")?,
+      '\r' => out.write_all(b"\\r")?,
+      c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+      c => write!(out, "{}", c)?
+    }
+  }
+
+  out.write_all(b"\"")
+}
+
+
+/// Run bgrep, outputting each match as an element of the JSON array to the given `out`.
+/// The array itself is opened and closed by `run`, spanning all files.
+/// Returns the number of matches.
+fn grep_json<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  json_elements: &mut usize,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let path = path.to_string();
+
+  let mut count = 0;
+
+  for (_, m) in find_iter(options, patterns, &path, buffer) {
+    // Elements are separated, rather than terminated, to avoid a trailing comma.
+    out.write_all(if *json_elements == 0 { b"\n" } else { b",\n" })?;
+
+    out.write_all(b"{\"path\": ")?;
+    write_json_string(out, &path)?;
+    write!(
+      out,
+      ", \"offset\": {}, \"length\": {}, \"bytes\": \"",
+      base + m.start(),
+      m.as_bytes().len()
+    )?;
+    for b in m.as_bytes() {
+      write!(out, "{:02x}", b)?;
+    }
+    out.write_all(b"\"}")?;
+    flush_match(out, options)?;
+
+    *json_elements += 1;
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+
+/// Run bgrep, outputting a radare2 flag creation command per match to the given `out`, as
+/// in `f match.0 @ 0x10`. The flags are numbered across all files, from `r2_flags`.
+/// Returns the number of matches.
+fn grep_r2_commands<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  r2_flags: &mut usize,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  let mut count = 0;
+
+  for (_, m) in find_iter(options, patterns, path, buffer) {
+    writeln!(out, "f match.{} @ 0x{:x}", r2_flags, base + m.start())?;
+    flush_match(out, options)?;
+
+    *r2_flags += 1;
+    count += 1;
+  }
+
+  Ok(count)
+}
+
+
+/// Output the files with the most matches, up to `count`, in descending order of matches.
+/// Files with the same number of matches keep the order they were searched in.
+fn write_top<O: Write>(out: &mut O, top: &mut [(String, usize)], count: usize) -> io::Result<()> {
+  top.sort_by(|(_, a), (_, b)| b.cmp(a)); // The sort is stable.
+
+  for (label, matches) in top.iter().take(count) {
+    writeln!(out, "{}: {}", label, matches)?;
+  }
+
+  Ok(())
+}
+
+
+/// Format a number of bytes as a human readable size, with one decimal, as in `1.5 MiB`.
+/// With `si`, the units are powers of 1000 instead of 1024, as in `1.5 MB`.
+fn human_size(bytes: u64, si: bool) -> String {
+  let (base, units) =
+    if si {
+      (1000.0, ["kB", "MB", "GB", "TB", "PB"])
+    } else {
+      (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB"])
+    };
+
+  if (bytes as f64) < base {
+    return format!("{} bytes", bytes);
+  }
+
+  let mut size = bytes as f64 / base;
+  let mut unit = 0;
+
+  while size >= base && unit + 1 < units.len() {
+    size /= base;
+    unit += 1;
+  }
+
+  format!("{:.1} {}", size, units[unit])
+}
+
+
+/// Output the histogram of match offsets, sorted by offset.
+fn write_histogram<O: Write>(out: &mut O, histogram: &BTreeMap<usize, u64>) -> io::Result<()> {
+  for (offset, count) in histogram {
+    writeln!(out, "0x{:x}: {}", offset, count)?;
+  }
+
+  Ok(())
+}
+
+
+/// Output the histogram of matched byte values, sorted by count in descending order, and
+/// then by value.
+fn write_value_histogram<O: Write>(out: &mut O, histogram: &BTreeMap<u8, u64>) -> io::Result<()> {
+  let mut values: Vec<_> = histogram.iter().collect();
+  values.sort_by(|(_, a), (_, b)| b.cmp(a)); // The sort is stable, keeping values in order.
+
+  for (value, count) in values {
+    writeln!(out, "0x{:02x}: {}", value, count)?;
+  }
+
+  Ok(())
+}
+
+
+/// Run bgrep over the buffer with the selected output mode, outputting to the given `out`.
+/// Returns the number of matches.
+fn grep_buffer<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  state: &mut State,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  // Masks and literals bypass the regex engine entirely:
+  if let Some(ref mask) = options.mask {
+    return grep_signature(out, options, mask.find_iter(buffer), path, base);
+  }
+
+  if let Some(ref literal) = options.literal {
+    return grep_signature(out, options, literal.find_iter(buffer), path, base);
+  }
+
+  match options.output {
+    args::Output::FileName => grep_filename (out, options, patterns, path, buffer),
+    args::Output::Status   => grep_status   (out, options, patterns, path, buffer),
+    args::Output::Bytes    => grep_bytes    (out, options, patterns, path, buffer),
+    args::Output::Offset   => grep_offset   (out, options, patterns, state, path, buffer, base),
+    args::Output::Histogram =>
+      Ok(grep_histogram(options, patterns, &mut state.histogram, path, buffer, base)),
+    args::Output::ValueHistogram =>
+      Ok(grep_value_histogram(options, patterns, &mut state.value_histogram, path, buffer)),
+    args::Output::Format(ref template) =>
+      grep_format(out, options, template, patterns, path, buffer, base),
+    args::Output::Replace(ref replacement) =>
+      grep_replace(out, options, replacement, patterns, buffer),
+    args::Output::Binary =>
+      grep_binary_out(out, options, patterns, path, buffer, base),
+    args::Output::Count =>
+      grep_count(out, options, patterns, path, buffer),
+    args::Output::Probe(ref offsets) =>
+      grep_probe(out, options, offsets, patterns, path, buffer, base),
+    args::Output::JsonArray =>
+      grep_json(out, options, patterns, &mut state.json_elements, path, buffer, base),
+    args::Output::R2Commands =>
+      grep_r2_commands(out, options, patterns, &mut state.r2_flags, path, buffer, base),
+    args::Output::Hexdump(cols) =>
+      grep_hexdump(out, options, cols, patterns, path, buffer, base),
+    // Only whether the file matched is relevant, the total is output by `run`.
+    args::Output::CountFiles =>
+      Ok(is_match(options, patterns, path, buffer) as usize),
+    args::Output::CString =>
+      grep_strings(out, options, cstrings(buffer), patterns, path, buffer, base),
+    args::Output::Strings(min_len) =>
+      grep_strings(out, options, printable_runs(buffer, min_len), patterns, path, buffer, base),
+    args::Output::BytesHex =>
+      grep_bytes_hex(out, options, patterns, path, buffer),
+    args::Output::ContextHex(context) =>
+      grep_context_hex(out, options, context, patterns, path, buffer, base),
+    args::Output::Extract(ref dir) =>
+      grep_extract(out, options, dir, patterns, path, buffer, base),
+    // Only the count is relevant, the ranking is output by `run`.
+    args::Output::Top(_) =>
+      Ok(count_matches(options, patterns, path, buffer).into_iter().sum())
+  }
+}
+
+
+/// Find the regions between the start and end delimiters. The end of a region is the first
+/// end delimiter after the start delimiter. An unterminated region extends to the end of
+/// the buffer.
+fn regions(start: &Regex, end: &Regex, buffer: &[u8]) -> Vec<Range<usize>> {
+  let mut regions = Vec::new();
+  let mut position = 0;
+
+  while position <= buffer.len() {
+    let begin = match start.find_at(buffer, position) {
+      Some(m) => m.end(),
+      None => break
+    };
+
+    match end.find_at(buffer, begin) {
+      Some(m) => {
+        regions.push(begin .. m.start());
+        // Make sure to advance, even if both delimiters match empty.
+        position = if m.end() > position { m.end() } else { position + 1 };
+      }
+
+      None => {
+        regions.push(begin .. buffer.len());
+        break;
+      }
+    }
+  }
+
+  regions
+}
+
+
+/// Run bgrep over the buffer, restricted to the regions delimited by `--within` if
+/// present. Offsets remain relative to the buffer.
+/// Returns the number of matches.
+fn grep_within<O: Write, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  state: &mut State,
+  path: P,
+  buffer: &[u8],
+  base: usize // The offset of the buffer in the input.
+) -> io::Result<usize> {
+  match patterns.within {
+    None => grep_buffer(out, options, patterns, state, path, buffer, base),
+
+    Some((ref start, ref end)) => {
+      let mut count = 0;
+
+      for region in regions(start, end, buffer) {
+        let base = base + region.start;
+        count += grep_buffer(out, options, patterns, state, &path, &buffer[region], base)?;
+      }
+
+      Ok(count)
+    }
+  }
+}
+
+
+/// Find where a window of the input can be cut, such that no match crosses the cut.
+/// Matches that start in the last `overlap` bytes of the window are left for the next
+/// window, as they could extend beyond the window.
+fn window_cut(pattern: &Alternation, window: &[u8], overlap: usize) -> usize {
+  let limit = window.len().saturating_sub(overlap);
+
+  match pattern.find_iter(window).find(|(_, m)| m.end() > limit) {
+    Some((_, m)) if m.start() < limit => m.end(),
+    _ => limit
+  }
+}
+
+
+/// Run bgrep over the reader in chunks, without loading the whole input in memory.
+/// Each window of the input is cut where no match crosses, and the remaining tail is
+/// carried over to the next window. Offsets remain absolute.
+/// Returns the number of matches.
+fn grep_stream<O: Write, R: Read, P: Display>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  state: &mut State,
+  path: P,
+  reader: &mut R,
+  buffer: &mut Vec<u8>
+) -> io::Result<usize> {
+  let mut count = 0;
+  let mut base = state.checkpoint.offset; // The offset of the window in the input.
+
+  // The file name, status and count outputs are done once per input, after a match is
+  // found or the input is exhausted.
+  let per_input = matches!(
+    options.output,
+    args::Output::FileName | args::Output::Status | args::Output::Count | args::Output::CountFiles
+  );
+
+  let mut matched = false;
+  let mut counts = Vec::new(); // The counts accumulated across windows.
+
+  let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+
+  let mut windows = 0; // The number of windows read so far.
+
+  loop {
+    // `read_to_end` already retries reads interrupted by signals, so errors are final.
+    let read = reader.take(options.chunk_size as u64)
+                     .read_to_end(buffer)
+                     .inspect_err(|_| eprintln!("Error: failed to read file '{}'", path))?;
+
+    let eof = read < options.chunk_size;
+
+    state.bytes += read as u64;
+
+    // Trim the ending newline if requested and present:
+    if eof && options.trim_ending_newline && buffer.last() == Some(&b'\n') {
+      buffer.pop();
+    };
+
+    // With `--stride`, only every Nth window is searched, and the others are dropped whole.
+    let searched = windows % options.stride == 0;
+    windows += 1;
+
+    // The last byte may be the ending newline to trim, so it is held back until the end of
+    // the input is known.
+    let held = (!eof && options.trim_ending_newline) as usize;
+    let window = &buffer[.. buffer.len() - held];
+
+    let cut =
+      if eof || !searched {
+        window.len()
+      } else {
+        window_cut(&patterns.regex, window, options.max_match_bytes)
+      };
+
+    options.progress_window.set(Some(progress::Window { base, total: state.input_len }));
+
+    if !searched {
+      // Skip the window entirely.
+    }
+    else if let args::Output::Count = options.output {
+      let window = count_matches(options, patterns, &path, &buffer[.. cut]);
+      counts.resize(window.len(), 0);
+
+      for (total, count) in counts.iter_mut().zip(window) {
+        *total += count;
+      }
+    }
+    else if per_input {
+      matched = is_match(options, patterns, &path, &buffer[.. cut]);
+    }
+    else if cut > 0 {
+      count += grep_buffer(out, options, patterns, state, &path, &buffer[.. cut], base)?;
+    }
+
+    if per_input {
+      if matched || eof { // There is no need to read further after a match.
+        return match options.output {
+          args::Output::Status => write_status(out, options, &path, matched),
+          args::Output::Count  => write_count(out, options, &path, &counts),
+          args::Output::CountFiles => Ok(matched as usize),
+          _ => write_filename(out, options, &path, matched ^ options.non_matching)
+        };
+      }
+    }
+    else if eof {
+      return Ok(count);
+    }
+
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+      eprintln!("Error: search timed out in file '{}'", path);
+      return Err(io::Error::from(io::ErrorKind::TimedOut));
+    }
+
+    buffer.drain(.. cut);
+    base += cut;
+
+    state.checkpoint.offset = base;
+    save_checkpoint(options, &state.checkpoint)?;
+  }
+}
+
+
+/// Save the checkpoint, if requested with `--resume-from`.
+fn save_checkpoint(options: &args::Options, checkpoint: &Checkpoint) -> io::Result<()> {
+  match options.resume_from {
+    Some(ref path) => resume::save(path, checkpoint).inspect_err(
+      |e| eprintln!("Error: failed to save checkpoint '{}', {}", path.display(), e)
+    ),
+    None => Ok(())
+  }
+}
+
+
+/// Skip the first `count` bytes of the reader, by reading and discarding them.
+fn skip<R: Read>(reader: &mut R, count: usize) -> io::Result<()> {
+  io::copy(&mut reader.take(count as u64), &mut io::sink()).map(|_| ())
+}
+
+
+/// Run bgrep with the given options, outputting to the given `out`.
+/// Error detail may be outputted to stderr.
+/// Returns the number of matches.
+fn run_file<O: Write, P: AsRef<Path>, B: AsMut<Vec<u8>>>(
+  out: &mut O,
+  options: &args::Options,
+  patterns: &Patterns,
+  state: &mut State,
+  path: P,
+  offset: usize, // The offset where the search starts.
+  buffer: &mut B
+) -> io::Result<usize> {
+  let buffer = buffer.as_mut();
+  let path = path.as_ref();
+
+  // Files that can't be canonicalized keep their path, and fail to open below.
+  let canonical = match options.absolute_paths {
+    true if path != Path::new(args::STDIN) => fs::canonicalize(path).ok(),
+    _ => None
+  };
+  let path = canonical.as_deref().unwrap_or(path);
+
+  // A time limit is only enforceable between chunks.
+  let mut chunked = options.device || options.timeout.is_some();
+
+  buffer.clear();
+
+  let (mut input, seek_result, path): (Box<dyn Read>, _, _) =
+    if path == Path::new(args::STDIN) { // Path::new is cost-free.
+      let mut stdin = io::stdin().lock();
+
+      // The size of stdin is unknown, so it must be read in chunks to respect a budget.
+      chunked |= options.max_memory.is_some();
+      state.input_len = 0;
+
+      let skip_result = skip(&mut stdin, offset);
+
+      let label = options.stdin_filename.as_ref().map_or("<stdin>", String::as_str);
+
+      (Box::new(stdin), skip_result, Path::new(label).display())
+    }
+    else {
+      let mut file = File::open(path)
+                          .inspect_err(|_| {
+                            eprintln!("Error: failed to open file '{}'", path.display());
+                          })?;
+
+      let file_size = file.metadata()
+                          .map(|m| m.len())
+                          .unwrap_or(0) as usize;
+
+      state.input_len = file_size;
+
+      // Files that don't fit the memory budget are read in chunks.
+      chunked |= options.max_memory.is_some_and(|max| file_size.saturating_sub(offset) > max);
+
+      // Resize buffer to the file size if it exceeds the current size.
+      // Currently, the strategy is to grow if needed, and otherwise do nothing.
+      // Considering we never shrink the buffer, this can be bad if the first file
+      // is huge and the others are small.
+      // The size of devices is unreliable, and they are read in chunks anyway.
+      if !chunked {
+        buffer.reserve(
+          file_size.saturating_sub(offset)
+                   .saturating_sub(buffer.len())
+        );
+      }
+
+      // Seek to the offset if possible, so that a large skip is instant. Otherwise,
+      // e.g. for pipes, fall back to reading and discarding.
+      let seek_result =
+        if offset == 0 {
+          Ok(())
+        } else {
+          file.seek(SeekFrom::Start(offset as u64))
+              .map(|_| ())
+              .or_else(|_| skip(&mut file, offset))
+        };
+
+      (Box::new(file), seek_result, path.display())
+    };
+
+  // As with `io::copy` in `skip`, reads interrupted by signals are retried by `read_to_end`.
+  let read = seek_result.and_then(
+    |_|
+      if chunked {
+        Ok(0)
+      } else {
+        input.read_to_end(buffer)
+      }
+  );
+
+  match read {
+    Ok(read) => {
+      state.bytes += read as u64;
+      state.input_len = state.input_len.max(offset + read); // The size of stdin is now known.
+    }
+    Err(e) => {
+      eprintln!("Error: failed to read file '{}'", path);
+      return Err(e);
+    }
+  }
+
+  if let args::Output::Top(_) = options.output {
+    state.label = path.to_string();
+  }
+
+  if chunked {
+    state.checkpoint.offset = offset; // The stream starts at the checkpoint.
+    let count = grep_stream(out, options, patterns, state, path, &mut input, buffer);
+
+    options.progress_window.set(None); // The next input may be read whole.
+    return count;
+  }
+
+
+  // Decode the hex text if requested:
+  if options.hex_input {
+    hex::decode_in_place(buffer).map_err(
+      |position| {
+        eprintln!("Error: invalid hex in file '{}' at byte {}", path, position);
+        io::Error::from(io::ErrorKind::InvalidData)
+      }
+    )?;
+
+    // Offsets from the end and their width are relative to the decoded bytes.
+    state.input_len = offset + buffer.len();
+  }
+
+
+  // Trim the ending newline if requested and present:
+  if options.trim_ending_newline && buffer.last() == Some(&b'\n') {
+    buffer.pop();
+  };
+
+
+  // Search only the PE section if requested, with offsets in the file or as RVAs.
+  if let Some(ref name) = options.pe_section {
+    let section = pe::find_section(buffer, name).map_err(
+      |message| {
+        eprintln!("Error: {} in file '{}'", message, path);
+        io::Error::from(io::ErrorKind::InvalidData)
+      }
+    )?;
+
+    let base = if options.pe_rva { section.virtual_address } else { section.range.start };
+
+    return grep_within(out, options, patterns, state, path, &buffer[section.range], base);
+  }
+
+
+  match options.record_format {
+    None => grep_within(out, options, patterns, state, path, buffer, offset),
+
+    // Search each record on its own, labeled with its index. Offsets are relative to the
+    // record.
+    Some(format) => {
+      let records = record::split(format, buffer).map_err(
+        |e| {
+          eprintln!("Error: malformed record {} in file '{}', {}", e.record, path, e.message);
+          io::Error::from(io::ErrorKind::InvalidData)
+        }
+      )?;
+
+      let mut count = 0;
+
+      for (ix, range) in records.into_iter().enumerate() {
+        let label = format!("{}[{}]", path, ix);
+        count += grep_within(out, options, patterns, state, label, &buffer[range], 0)?;
+      }
+
+      Ok(count)
+    }
+  }
+}
 
-  buffer.clear();
+/// Whether the pattern only matches the empty string, at every position, as the empty
+/// pattern does. This also covers the patterns that are empty once assembled, such as
+/// `(?i:)`, while assertions like `^` only match at some positions.
+fn is_empty(regex: &Regex) -> bool {
+  (0 ..= u8::MAX).all(
+    |b| regex.find_iter(&[b, b]).map(|m| m.start() .. m.end()).eq([0 .. 0, 1 .. 1, 2 .. 2])
+  )
+}
 
-  let (read_result, path) =
-    if path == Path::new(args::STDIN) { // Path::new is cost-free.
-      (io::stdin().lock().read_to_end(buffer), Path::new("<stdin>").display())
-    }
-    else {
-      let mut file = File::open(path)
-                          .map_err(|e| {
-                            eprintln!("Error: failed to open file '{}'", path.display());
-                            e
-                          })?;
 
-      // Resize buffer to the file size if it exceeds the current size.
-      // Currently, the strategy is to grow if needed, and otherwise do nothing.
-      // Considering we never shrink the buffer, this can be bad if the first file
-      // is huge and the others are small.
-      let file_size = file.metadata()
-                          .map(|m| m.len())
-                          .unwrap_or(0) as usize;
-      buffer.reserve(
-        file_size.saturating_sub(buffer.len())
+/// Compile the patterns with the given options, reporting errors to stderr.
+fn compile_patterns(patterns: &[String], options: &args::Options) -> io::Result<Patterns> {
+  let compile = |pattern: &String| build_pattern(pattern, options).map_err(
+    |e| {
+      match e {
+        regex::Error::CompiledTooBig(limit) if options.dfa_limit.is_some() => eprintln!(
+          "Error: pattern '{}' is too large, the compiled program exceeds the --dfa-limit of \
+           {} bytes",
+          pattern,
+          limit
+        ),
+        e => eprintln!("Error: invalid pattern '{}', {}", pattern, e)
+      }
+
+      io::ErrorKind::InvalidInput
+    }
+  );
+
+  // Each pattern is compiled on its own, which reports errors precisely, and tells which
+  // pattern produced a match. Pasting them in a single regex would also break patterns
+  // that are only valid on their own.
+  let compile_wrapped = |pattern: &String| {
+    let pattern = format!("{}{}{}", options.wrap_prefix, pattern, options.wrap_suffix);
+    let regex = compile(&pattern)?;
+
+    // An empty pattern matches everywhere, which is rarely intended.
+    if is_empty(&regex) && !options.allow_empty_pattern {
+      eprintln!(
+        "Error: pattern '{}' is empty, and would match at every position. Use \
+         --allow-empty-pattern to search for it anyway",
+        pattern
       );
+      return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
 
-      (file.read_to_end(buffer), path.display())
-    };
+    Ok(regex)
+  };
 
-  if let Err(e) = read_result {
-    eprintln!("Error: failed to read file '{}'", path);
-    return Err(e);
+  let regex = patterns.iter()
+                      .map(compile_wrapped)
+                      .collect::<io::Result<Box<[Regex]>>>()
+                      .map(Alternation::new)?;
+
+  if let Some(group) = options.offset_group {
+    if group >= regex.captures_len() {
+      eprintln!("Error: the patterns have no capture group {}", group);
+      return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
   }
 
+  let within = match options.within {
+    Some((ref start, ref end)) => Some((compile(start)?, compile(end)?)),
+    None => None
+  };
 
-  // Trim the ending newline if requested and present:
-  if options.trim_ending_newline && buffer.last() == Some(&b'\n') {
-    buffer.pop();
+  let anchor = match options.relative_to {
+    Some(ref anchor) => Some(compile(anchor)?),
+    None => None
+  };
+
+  let anchored = |pattern: &String| -> io::Result<Anchored> {
+    Ok(
+      Anchored {
+        at_start: compile(&format!(r"\A(?:{})", pattern))?,
+        after_byte: compile(&format!(r"\A(?s-u:.)(?:{})", pattern))?
+      }
+    )
+  };
+
+  // The lookahead is anchored to the end of each match.
+  let not_followed_by = match options.not_followed_by {
+    Some(ref pattern) => Some(anchored(pattern)?),
+    None => None
+  };
+
+  let probes = match options.output {
+    args::Output::Probe(_) => Some(
+      patterns.iter()
+              .map(|p| anchored(&format!("{}{}{}", options.wrap_prefix, p, options.wrap_suffix)))
+              .collect::<io::Result<Box<[Anchored]>>>()?
+    ),
+    _ => None
   };
 
+  Ok(Patterns { regex, within, anchor, not_followed_by, probes })
+}
+
+
+/// Compile each pattern on its own, with the file its output is written to, as specified by
+/// `--output-by-pattern`. The files are named after the index of the pattern, as in `p0.txt`.
+fn open_sinks(
+  dir: &Path,
+  patterns: &[String],
+  options: &args::Options
+) -> io::Result<Vec<(Patterns, io::BufWriter<File>)>> {
+  fs::create_dir_all(dir).inspect_err(
+    |e| eprintln!("Error: failed to create directory '{}', {}", dir.display(), e)
+  )?;
+
+  patterns.iter()
+          .enumerate()
+          .map(|(ix, pattern)| {
+            let compiled = compile_patterns(std::slice::from_ref(pattern), options)?;
+
+            let path = dir.join(format!("p{}.txt", ix));
+            let sink = File::create(&path).inspect_err(
+              |e| eprintln!("Error: failed to create file '{}', {}", path.display(), e)
+            )?;
+
+            Ok((compiled, io::BufWriter::new(sink)))
+          })
+          .collect()
+}
 
-  let matched = match options.output {
-    args::Output::FileName => grep_filename (out, options, pattern, path, buffer),
-    args::Output::Bytes    => grep_bytes    (out, options, pattern, path, buffer),
-    args::Output::Offset   => grep_offset   (out, options, pattern, path, buffer)
-  }?;
 
-  Ok(matched)
+/// Advance the checkpoint past the current file, saving it if requested.
+fn advance_checkpoint(options: &args::Options, state: &mut State) -> io::Result<()> {
+  state.checkpoint = Checkpoint { files: state.checkpoint.files + 1, offset: 0 };
+  save_checkpoint(options, &state.checkpoint)
 }
 
+
 /// Run bgrep with the given args, outputting to stdout.
 /// Error detail may be outputted to stderr.
 /// Returns whether there was a match.
 pub fn run<O: Write>(args: Args, out: &mut O) -> io::Result<bool> {
   // Deconstruct to split ownership:
-  let Args { options, pattern, files } = args;
+  let Args { options, patterns, manifest, files } = args;
 
+  // The compiled patterns, keyed by the indices of the manifest entries that apply. Without
+  // a manifest, the patterns from the arguments are always under the empty key.
+  let mut compiled: HashMap<Vec<usize>, Patterns> = HashMap::new();
 
-  let pattern = build_pattern(&pattern, &options).map_err(
-    |e| {
-      eprintln!("Error: invalid pattern '{}', {}", pattern, e);
-      io::ErrorKind::InvalidInput
-    }
-  )?;
+  match manifest {
+    None => { compiled.insert(Vec::new(), compile_patterns(&patterns, &options)?); }
+    Some(ref entries) =>
+      for entry in entries.iter() { // Report invalid patterns before searching anything.
+        compile_patterns(std::slice::from_ref(&entry.pattern), &options)?;
+      }
+  }
+
+  let mut state = State::default();
 
+  let start_time = Instant::now();
 
-  // Reuse the same buffer for all the files, minimizing allocations.
+
+  // Reuse the same buffer for all the files, minimizing allocations, unless disabled with
+  // `--no-buffer-reuse`.
   let mut buffer = Vec::<u8>::new();
 
   // The next part is a bit complicated:
@@ -291,28 +2074,593 @@ pub fn run<O: Write>(args: Args, out: &mut O) -> io::Result<bool> {
   //   because it should only happen when outputting, and no matches means no output.
   //
   // An error code corresponding to the last error. Common errors are `NotFound` and
-  // `PermissionDenied`.
+  // `PermissionDenied`. With `--fail-fast`, the first error stops the processing, except
+  // for `TimedOut`, as `--timeout` moves on to the next file.
 
   // We need to store the last generated error if any, or whether there was a match:
   let mut result = Ok(false);
 
   // Converting to vec to use the owned iterator. Box<[T]> has no owned iterator.
-  for file in files.to_vec() {
+  let mut files = files.into_vec();
+
+  if options.reverse {
+    files.reverse();
+  }
+
+  // Resume from the saved position, if any. The files must be the same as in the
+  // interrupted scan.
+  if let Some(ref path) = options.resume_from {
+    let checkpoint = resume::load(path).inspect_err(
+      |e| eprintln!("Error: failed to load checkpoint '{}', {}", path.display(), e)
+    )?;
+
+    state.checkpoint = checkpoint.unwrap_or_default();
+  }
+
+  let resumed = state.checkpoint.files.min(files.len());
+
+  // The JSON array spans all files, and is closed with the summary.
+  if let args::Output::JsonArray = options.output {
+    out.write_all(b"[")?;
+  }
+
+  // The extraction directory is created once, for all files.
+  if let args::Output::Extract(ref dir) = options.output {
+    fs::create_dir_all(dir).inspect_err(
+      |e| eprintln!("Error: failed to create directory '{}', {}", dir.display(), e)
+    )?;
+  }
+
+  // With `--output-by-pattern`, each pattern is searched on its own, and writes to its own
+  // file in the directory.
+  let mut sinks = match options.output_by_pattern {
+    Some(ref dir) => Some(open_sinks(dir, &patterns, &options)?),
+    None => None
+  };
+
+  let mut completed = true; // Whether all files were processed.
+  let mut all_matched = true; // Whether every searched file matched, for `--all-match`.
+
+  // The modification times of the files searched by previous scans.
+  let mut mtimes = match options.only_files_changed {
+    Some(ref path) => Some(
+      mtime::load(path).inspect_err(
+        |e| eprintln!("Error: failed to load modification times '{}', {}", path.display(), e)
+      )?
+    ),
+    None => None
+  };
+
+  for file in files.into_iter().skip(resumed) {
     let file: PathBuf = file; // Make sure we are using an owned iterator.
 
-    match run_file(out, &options, &pattern, &file, &mut buffer) {
-      Ok(false) => (),
-      Ok(true) => result = result.map(|_| true), // Set to true if there was no error.
+    let offset = options.offset.max(state.checkpoint.offset);
+
+    if options.no_buffer_reuse {
+      buffer = Vec::new(); // Start each file from a fresh allocation.
+    }
+
+    // With `--only-files-changed`, files whose modification time was recorded in the last
+    // scan are skipped. Stdin is always searched.
+    let mtime = match mtimes {
+      Some(_) if file != Path::new(args::STDIN) => mtime::of(&file).ok(),
+      _ => None
+    };
+
+    let unchanged = mtime.is_some() && mtimes.as_ref().and_then(|m| m.get(&file)) == mtime.as_ref();
+
+    // With `--type`, files with other extensions are skipped. Stdin is always searched.
+    let excluded = options.types.as_ref().is_some_and(
+      |types| file != Path::new(args::STDIN)
+           && !file.extension()
+                   .and_then(OsStr::to_str)
+                   .is_some_and(|extension| types.contains(&extension.to_ascii_lowercase()))
+    );
+
+    // Select the patterns for the file. With a manifest, all the entries whose glob
+    // matches apply, and files without any are skipped.
+    let key = match manifest {
+      _ if unchanged || excluded => None,
+      None => Some(Vec::new()),
+      Some(ref entries) => Some(
+        (0 .. entries.len())
+          .filter(|&ix| manifest::matches(&entries[ix].glob, &file))
+          .collect::<Vec<usize>>()
+      ).filter(|key| !key.is_empty())
+    };
+
+    let key = match key {
+      Some(key) => key,
+      None => {
+        if let Err(e) = advance_checkpoint(&options, &mut state) {
+          result = Err(e);
+          completed = false;
+          break;
+        }
+
+        continue;
+      }
+    };
+
+    if let (false, Some(ref entries)) = (compiled.contains_key(&key), &manifest) {
+      let selected: Vec<String> = key.iter()
+                                     .map(|&ix| entries[ix].pattern.clone())
+                                     .collect();
+      compiled.insert(key.clone(), compile_patterns(&selected, &options)?);
+    }
+
+    let patterns = &compiled[&key];
+
+    let searched = match sinks {
+      None => run_file(out, &options, patterns, &mut state, &file, offset, &mut buffer),
+      Some(ref mut sinks) => sinks.iter_mut().try_fold(
+        0,
+        |total, (patterns, sink)| {
+          let count = run_file(sink, &options, patterns, &mut state, &file, offset, &mut buffer)?;
+          Ok(total + count)
+        }
+      )
+    };
+
+    match searched {
+      Ok(count) => {
+        state.files += 1;
+        state.matches += count;
+
+        if let (Some(mtimes), Some(mtime)) = (mtimes.as_mut(), mtime) {
+          mtimes.insert(file.clone(), mtime);
+        }
+
+        if count > 0 {
+          if let args::Output::Top(_) = options.output {
+            state.top.push((std::mem::take(&mut state.label), count));
+          }
+
+          state.matched_files += 1;
+          result = result.map(|_| true); // Set to true if there was no error.
+        }
+        else if options.all_match {
+          eprintln!("bgrep: no match in file '{}'", file.display());
+          all_matched = false;
+        }
+      }
       Err(e) =>
         if e.kind() == io::ErrorKind::BrokenPipe {
           // Bail early on `BronkenPipe`, conserving the previous error if any.
           result = result.map(|_| true); // `BrokenPipe` only happens when outputting,
-          break;                         // and that means there was a match.
+          completed = false;             // and that means there was a match.
+          break;
         } else {
-          result = Err(e) // Store the error and move on.
+          state.errors += 1;
+          let timed_out = e.kind() == io::ErrorKind::TimedOut;
+          result = Err(e); // Store the error and move on, unless failing fast.
+
+          if options.fail_fast && !timed_out {
+            completed = false;
+            break;
+          }
         }
     }
+
+    // Stop once `--max-matches-total` is reached, leaving the rest to resume from. The file
+    // may have more matches, so the checkpoint stays on it, to be searched again.
+    if options.remaining_matches.as_ref().map(Cell::get) == Some(0) {
+      if let Err(e) = save_checkpoint(&options, &state.checkpoint) {
+        result = Err(e);
+      }
+
+      completed = false;
+      break;
+    }
+
+    if let Err(e) = advance_checkpoint(&options, &mut state) {
+      result = Err(e);
+      completed = false;
+      break;
+    }
+  }
+
+  for (_, sink) in sinks.iter_mut().flatten() {
+    if let Err(e) = sink.flush() {
+      result = Err(e);
+    }
+  }
+
+  if let (Some(ref path), Some(ref mtimes)) = (&options.only_files_changed, &mtimes) {
+    if let Err(e) = mtime::save(path, mtimes) {
+      eprintln!("Error: failed to save modification times '{}', {}", path.display(), e);
+      result = Err(e);
+    }
+  }
+
+  // A complete scan leaves nothing to resume.
+  if let (true, Some(ref path)) = (completed, &options.resume_from) {
+    if let Err(e) = fs::remove_file(path) {
+      eprintln!("Error: failed to remove checkpoint '{}', {}", path.display(), e);
+      result = Err(e);
+    }
+  }
+
+
+  if options.summary_json {
+    let summary = format!(
+      "{{\"files\": {}, \"matched_files\": {}, \"errors\": {}, \"bytes\": {}, \
+       \"matches\": {}, \"seconds\": {:.3}}}\n",
+      state.files,
+      state.matched_files,
+      state.errors,
+      state.bytes,
+      state.matches,
+      start_time.elapsed().as_secs_f64()
+    );
+
+    // The summary never goes to stdout, which is reserved for the matches.
+    let written = match options.summary_file {
+      Some(ref path) => fs::write(path, summary).inspect_err(
+        |e| eprintln!("Error: failed to write summary '{}', {}", path.display(), e)
+      ),
+      None => io::stderr().write_all(summary.as_bytes())
+    };
+
+    if let Err(e) = written {
+      result = Err(e);
+    }
+  }
+
+  if options.count_bytes_total {
+    eprintln!("bgrep: {} read", human_size(state.bytes, options.si));
+  }
+
+  if options.report {
+    eprintln!(
+      "bgrep: {} matches in {} files, {:.3} seconds",
+      state.matches,
+      state.files,
+      start_time.elapsed().as_secs_f64()
+    );
+  }
+
+
+  // Output what was accumulated across files:
+  let summary = match options.output {
+    args::Output::Histogram => write_histogram(out, &state.histogram),
+    args::Output::ValueHistogram => write_value_histogram(out, &state.value_histogram),
+    args::Output::Offset if options.offset_separator.is_some() && state.offsets > 0 =>
+      writeln!(out),
+    args::Output::JsonArray => out.write_all(b"\n]\n"),
+    args::Output::CountFiles => writeln!(out, "{}", state.matched_files),
+    args::Output::Top(count) => write_top(out, &mut state.top, count),
+    args::Output::Count if options.count_total => writeln!(out, "total: {}", state.matches),
+    _ => Ok(())
+  };
+
+  match summary {
+    Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+    _ => match result {
+      Ok(false) if options.require_match => {
+        eprintln!("Error: no match found in any file");
+        Err(io::Error::other(NoMatch))
+      }
+      result => result.map(|matched| matched && all_matched || options.no_match_exit_zero)
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+
+  /// Parse the given arguments, which follow the program name.
+  fn parse(args: &[&str]) -> Args {
+    match args::parse(std::iter::once("bgrep").chain(args.iter().copied())) {
+      Ok(args::Command::Grep(args)) => *args,
+      _ => panic!("invalid arguments {:?}", args)
+    }
+  }
+
+
+  /// Run bgrep over the buffer with the given arguments, returning the output.
+  fn grep(args: &[&str], buffer: &[u8]) -> String {
+    let Args { options, patterns, .. } = parse(args);
+    let patterns = compile_patterns(&patterns, &options).expect("invalid pattern");
+    let mut state = State { input_len: buffer.len(), ..State::default() };
+
+    let mut out = Vec::new();
+    grep_buffer(&mut out, &options, &patterns, &mut state, "input", buffer, 0).unwrap();
+
+    String::from_utf8(out).expect("non UTF-8 output")
+  }
+
+
+  #[test]
+  fn dfa_limit_rejects_large_repetition() {
+    let Args { options, patterns, .. } = parse(&["--dfa-limit", "1K", "a{1000}"]);
+
+    assert_eq!(
+      compile_patterns(&patterns, &options).err().map(|e| e.kind()),
+      Some(io::ErrorKind::InvalidInput)
+    );
+  }
+
+
+  #[test]
+  fn dfa_limit_matches_large_repetition() {
+    let buffer = [&b"xx"[..], &[b'a'; 1200]].concat();
+
+    assert_eq!(grep(&["-b", "--dfa-limit", "4M", "a{1000}"], &buffer), "0x2\n");
+  }
+
+
+  /// Compile the patterns given with the arguments, returning the error kind if any.
+  fn compile_error(args: &[&str]) -> Option<io::ErrorKind> {
+    let Args { options, patterns, .. } = parse(args);
+    compile_patterns(&patterns, &options).err().map(|e| e.kind())
+  }
+
+
+  #[test]
+  fn empty_pattern_rejected_in_every_mode() {
+    for mode in &["-l", "-o", "-b", "-c"] {
+      assert_eq!(compile_error(&[mode, ""]), Some(io::ErrorKind::InvalidInput), "{}", mode);
+      assert_eq!(compile_error(&[mode, "--pattern-spec", "i:"]), Some(io::ErrorKind::InvalidInput));
+    }
+  }
+
+
+  #[test]
+  fn empty_pattern_allowed() {
+    let grep = |mode| grep(&[mode, "--allow-empty-pattern", ""], b"ab");
+
+    assert_eq!(grep("-l"), "input\n");
+    assert_eq!(grep("-o"), "\n\n\n");
+    assert_eq!(grep("-b"), "0x0\n0x1\n0x2\n");
+    assert_eq!(grep("-c"), "3\n");
+  }
+
+
+  #[test]
+  fn empty_pattern_wrapped() {
+    assert_eq!(compile_error(&["--wrap-prefix", "a", ""]), None);
+    assert_eq!(compile_error(&["x*"]), None);
+    assert_eq!(grep(&["-b", "--wrap-prefix", "b", ""], b"ab"), "0x1\n");
+  }
+
+
+  /// Run bgrep with the given arguments, returning the result and the output.
+  fn run_args(args: &[&str]) -> (io::Result<bool>, String) {
+    let mut out = Vec::new();
+    let result = run(parse(args), &mut out);
+
+    (result, String::from_utf8(out).expect("non UTF-8 output"))
+  }
+
+
+  /// A path in the temporary directory, unique to this process.
+  fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("bgrep-{}-{}", std::process::id(), name))
+  }
+
+
+  #[test]
+  fn errors_stop_only_with_fail_fast() {
+    let good = temp_path("good");
+    let missing = temp_path("missing");
+    fs::write(&good, b"abc").unwrap();
+
+    let (good, missing) = (good.to_str().unwrap(), missing.to_str().unwrap());
+    let line = format!("{}: 1\n", good);
+
+    let (result, out) = run_args(&["-c", "b", good, missing, good]);
+    assert_eq!(result.map_err(|e| e.kind()).err(), Some(io::ErrorKind::NotFound));
+    assert_eq!(out, line.repeat(2));
+
+    let (result, out) = run_args(&["-c", "--fail-fast", "b", good, missing, good]);
+    assert_eq!(result.map_err(|e| e.kind()).err(), Some(io::ErrorKind::NotFound));
+    assert_eq!(out, line);
+
+    fs::remove_file(good).unwrap();
+  }
+
+
+  #[test]
+  fn timeouts_move_on_with_fail_fast() {
+    let slow = temp_path("slow");
+    let fast = temp_path("fast");
+    fs::write(&slow, [0; 64]).unwrap();
+    fs::write(&fast, b"xxab").unwrap();
+
+    let (slow, fast) = (slow.to_str().unwrap(), fast.to_str().unwrap());
+    let chunks = ["--chunk-size", "16", "--max-match-bytes", "4"];
+
+    // The first file spans several chunks, so it times out after the first one.
+    let (result, out) = run_args(
+      &[&["-b", "--fail-fast", "--timeout", "0"][..], &chunks, &["ab", slow, fast]].concat()
+    );
+    assert_eq!(result.map_err(|e| e.kind()).err(), Some(io::ErrorKind::TimedOut));
+    assert_eq!(out, format!("{}: 0x2\n", fast));
+
+    fs::remove_file(slow).unwrap();
+    fs::remove_file(fast).unwrap();
+  }
+
+
+  #[test]
+  fn files_without_match_differ_from_inverse() {
+    let list = |args: &[&str]| -> Vec<&str> {
+      [&b"aab"[..], b"bbb", b"aaa"].iter()
+                                  .filter(|buffer| !grep(args, buffer).is_empty())
+                                  .map(|buffer| std::str::from_utf8(buffer).unwrap())
+                                  .collect()
+    };
+
+    // Without a match anywhere, as opposed to with a region that doesn't match.
+    assert_eq!(list(&["-L", "a"]), ["bbb"]);
+    assert_eq!(list(&["-l", "-v", "a"]), ["aab", "bbb"]);
+    assert_eq!(list(&["-l", "a"]), ["aab", "aaa"]);
+
+    let args = ["bgrep", "-L", "-v", "a"];
+    assert!(args::parse(args.iter().copied()).is_err());
+  }
+
+
+  #[test]
+  fn with_length_fixed() {
+    assert_eq!(
+      grep(&["-b", "--with-length", "x."], b"xaaxaxa"),
+      "0x0 (2 bytes)\n0x3 (2 bytes)\n0x5 (2 bytes)\n"
+    );
+  }
+
+
+  #[test]
+  fn with_length_variable() {
+    assert_eq!(
+      grep(&["-b", "--with-length", "a+"], b"xaaxaxa"),
+      "0x1 (2 bytes)\n0x4 (1 byte)\n0x6 (1 byte)\n"
+    );
+  }
+
+
+  #[test]
+  fn dot_matches_newline_by_default() {
+    assert_eq!(grep(&["-b", "a.b"], b"a\nb a-b"), "0x0\n0x4\n");
+    assert_eq!(grep(&["-b", r"a\nb"], b"a\nb a-b"), "0x0\n");
+  }
+
+
+  #[test]
+  fn literal_newline_keeps_dot_from_newlines() {
+    assert_eq!(grep(&["-b", "--literal-newline", "a.b"], b"a\nb a-b"), "0x4\n");
+    assert_eq!(grep(&["-b", "--literal-newline", r"a\nb"], b"a\nb a-b"), "0x0\n");
+    assert_eq!(grep(&["-b", "--literal-newline", "(?s)a.b"], b"a\nb a-b"), "0x0\n0x4\n");
+  }
+
+
+  /// A reader whose first read is interrupted, as by a signal.
+  struct Interrupted<'a> {
+    data: &'a [u8],
+    interrupted: bool
+  }
+
+
+  impl<'a> Read for Interrupted<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      if !self.interrupted {
+        self.interrupted = true;
+        return Err(io::Error::from(io::ErrorKind::Interrupted));
+      }
+
+      self.data.read(buf)
+    }
+  }
+
+
+  #[test]
+  fn interrupted_read_retried() {
+    let Args { options, patterns, .. } = parse(
+      &["-b", "--device", "--chunk-size", "16", "--max-match-bytes", "4", "ab"]
+    );
+    let patterns = compile_patterns(&patterns, &options).unwrap();
+    let data = [&b"ab"[..], &[b'-'; 20], b"ab"].concat();
+    let mut reader = Interrupted { data: &data, interrupted: false };
+
+    let mut out = Vec::new();
+    let mut state = State::default();
+    let count = grep_stream(
+      &mut out, &options, &patterns, &mut state, "input", &mut reader, &mut Vec::new()
+    );
+
+    assert_eq!(count.unwrap(), 2);
+    assert_eq!(String::from_utf8(out).unwrap(), "0x0\n0x16\n");
+  }
+
+
+  #[test]
+  fn hex_input_offsets_from_decoded_end() {
+    let path = temp_path("hex");
+    fs::write(&path, b"00616200000000ab").unwrap(); // Eight bytes, as sixteen digits.
+    let path = path.to_str().unwrap();
+
+    assert_eq!(run_args(&["-b", "--hex-input", "--offset-from-end", "ab", path]).1, "-0x7\n");
+    assert_eq!(run_args(&["-b", "--hex-input", "--offset-width", "auto", "ab", path]).1, "0x1\n");
+
+    fs::remove_file(path).unwrap();
+  }
+
+
+  #[test]
+  fn pattern_spec_with_verbose_comment() {
+    assert_eq!(grep(&["-b", "--pattern-spec", "x:f o o # c"], b"xfooy"), "0x1\n");
+    assert_eq!(grep(&["-b", "--pattern-spec", "xi:F o o # c", "-e", "y"], b"xfooy"), "0x1\n0x4\n");
+    assert_eq!(grep(&["-b", "--pattern-spec", "i-x:O$"], b"foo"), "0x2\n"); // No newline added.
+  }
+
+
+  #[test]
+  fn signatures_reject_offset_formats() {
+    let formats = ["--with-length", "--offset-from-end", "--offset-width=8", "--span"];
+
+    for format in &formats {
+      let args = ["bgrep", "-b", "--mask", "ff", format, "61"];
+      assert!(args::parse(args.iter().copied()).is_err(), "{}", format);
+
+      let args = ["bgrep", "-b", "--literal", format, "61"];
+      assert!(args::parse(args.iter().copied()).is_err(), "{}", format);
+    }
+  }
+
+
+  #[test]
+  fn gaps_without_match() {
+    assert_eq!(grep(&["-b", "--gaps", "ab"], b"xxxx"), "0x0-0x4\n");
+    assert_eq!(grep(&["-b", "--gaps", "ab"], b"xabx"), "0x0-0x1\n0x3-0x4\n");
+    assert_eq!(grep(&["-b", "--gaps", "ab"], b"abab"), "");
+
+    // The exit status follows the gaps, not the matches.
+    let path = temp_path("gaps");
+    fs::write(&path, b"xxxx").unwrap();
+    assert_eq!(run_args(&["-b", "--gaps", "ab", path.to_str().unwrap()]).0.ok(), Some(true));
+
+    fs::write(&path, b"abab").unwrap();
+    assert_eq!(run_args(&["-b", "--gaps", "ab", path.to_str().unwrap()]).0.ok(), Some(false));
+
+    fs::remove_file(path).unwrap();
+  }
+
+
+  #[test]
+  fn format_missing_groups_empty() {
+    // %2 doesn't participate in the first match, nor %1 in the second, and %5 doesn't exist.
+    assert_eq!(grep(&["--format", r"[%1|%2|%5]\n", "a(b)?(c)?"], b"ab ac"), "[b||]\n[|c|]\n");
+  }
+
+
+  #[test]
+  fn not_followed_by_sees_preceding_byte() {
+    assert_eq!(grep(&["-b", "--not-followed-by", "b", "a"], b"abac"), "0x2\n");
+    assert_eq!(grep(&["-b", "--not-followed-by", r"\b", "[a-z]"], b"ab cd"), "0x0\n0x3\n");
+    assert_eq!(grep(&["-b", "--not-followed-by", r"\B", "[a-z]"], b"ab cd"), "0x1\n0x4\n");
   }
 
-  result
+
+  #[test]
+  fn probe_offsets_anchored() {
+    let offsets = temp_path("offsets");
+    fs::write(&offsets, b"0 1 4 5 9").unwrap();
+    let probe = |pattern| grep(&["--probe-offsets", offsets.to_str().unwrap(), pattern], b"xab ab");
+
+    // The byte before each offset is seen by the assertions.
+    assert_eq!(
+      probe(r"\bab"),
+      "0x0: NO-MATCH\n0x1: NO-MATCH\n0x4: MATCH\n0x5: NO-MATCH\n0x9: NO-MATCH\n"
+    );
+    assert_eq!(
+      probe("b|ab"),
+      "0x0: NO-MATCH\n0x1: MATCH\n0x4: MATCH\n0x5: MATCH\n0x9: NO-MATCH\n"
+    );
+
+    fs::remove_file(offsets).unwrap();
+  }
 }
@@ -0,0 +1,92 @@
+/// The value of a hex digit.
+fn digit(c: u8) -> Option<u8> {
+  match c {
+    b'0' ..= b'9' => Some(c - b'0'),
+    b'a' ..= b'f' => Some(c - b'a' + 10),
+    b'A' ..= b'F' => Some(c - b'A' + 10),
+    _ => None
+  }
+}
+
+
+/// Decode a hex encoded buffer in place, ignoring ASCII whitespace.
+/// Returns the position of the offending byte if the buffer is not valid hex. A dangling
+/// digit is reported at the end of the buffer.
+pub fn decode_in_place(buffer: &mut Vec<u8>) -> Result<(), usize> {
+  let mut len = 0; // The length of the decoded prefix.
+  let mut high = None; // The pending high nibble.
+
+  for ix in 0 .. buffer.len() {
+    let c = buffer[ix];
+
+    if c.is_ascii_whitespace() {
+      continue;
+    }
+
+    let value = digit(c).ok_or(ix)?;
+
+    match high.take() {
+      None => high = Some(value),
+      Some(h) => {
+        buffer[len] = h << 4 | value; // Never overwrites unread bytes, as len <= ix / 2.
+        len += 1;
+      }
+    }
+  }
+
+  if high.is_some() {
+    return Err(buffer.len());
+  }
+
+  buffer.truncate(len);
+
+  Ok(())
+}
+
+
+/// Decode a hex encoded string, ignoring ASCII whitespace.
+/// Returns the position of the offending byte if the string is not valid hex.
+pub fn decode<S: AsRef<str>>(string: S) -> Result<Vec<u8>, usize> {
+  let mut bytes = string.as_ref().as_bytes().to_vec();
+  decode_in_place(&mut bytes)?;
+  Ok(bytes)
+}
+
+
+/// Translate a byte glob to the equivalent regex. The glob is made of hex bytes, `?` for
+/// any single byte and `*` for any sequence of bytes, the shortest one. ASCII whitespace is
+/// ignored.
+/// Returns the position of the offending byte if the glob is invalid. A dangling digit is
+/// reported at the end of the glob.
+pub fn glob_to_regex<S: AsRef<str>>(glob: S) -> Result<String, usize> {
+  let glob = glob.as_ref().as_bytes();
+
+  let mut regex = String::new();
+  let mut high = None; // The pending high nibble.
+
+  for (ix, &c) in glob.iter().enumerate() {
+    match c {
+      _ if c.is_ascii_whitespace() => (),
+
+      // Wildcards can't split a byte. The `s` flag makes `.` match newlines regardless
+      // of `--literal-newline`.
+      b'?' if high.is_none() => regex.push_str("(?s:.)"),
+      b'*' if high.is_none() => regex.push_str("(?s:.*?)"),
+
+      _ => {
+        let value = digit(c).ok_or(ix)?;
+
+        match high.take() {
+          None => high = Some(value),
+          Some(h) => regex.push_str(&format!("\\x{:02x}", h << 4 | value)) // Always literal.
+        }
+      }
+    }
+  }
+
+  if high.is_some() {
+    return Err(glob.len());
+  }
+
+  Ok(regex)
+}
@@ -0,0 +1,186 @@
+/// The base of the rolling hash. Arithmetic wraps, i.e. it is modulo 2^64, and candidate
+/// windows are always compared, so collisions only cost time.
+const BASE: u64 = 257;
+
+
+/// A literal byte signature, as specified by `--literal`, matched with a rolling hash
+/// (Rabin-Karp) instead of the regex engine.
+#[derive(Debug)]
+pub struct Literal {
+  bytes: Vec<u8>,
+  hash: u64,  // The hash of the bytes.
+  // The reversed bytes and their hash, also matched with `--also-reversed`. Absent for
+  // palindromes, which already match.
+  reversed: Option<(Vec<u8>, u64)>,
+  power: u64  // BASE^(len - 1), the weight of the byte leaving the window.
+}
+
+
+/// Hash the bytes, with the first byte as the most significant digit.
+fn hash(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0, |hash, &b| hash.wrapping_mul(BASE).wrapping_add(b as u64))
+}
+
+
+impl Literal {
+  /// Build the signature, which must not be empty. With `also_reversed`, the reversed
+  /// bytes match as well, in the same pass.
+  pub fn new(bytes: Vec<u8>, also_reversed: bool) -> Literal {
+    assert!(!bytes.is_empty(), "empty literal");
+
+    let power = (1 .. bytes.len()).fold(1u64, |power, _| power.wrapping_mul(BASE));
+
+    let reversed: Vec<u8> = bytes.iter().rev().copied().collect();
+    let reversed =
+      if also_reversed && reversed != bytes {
+        let reversed_hash = hash(&reversed);
+        Some((reversed, reversed_hash))
+      } else {
+        None
+      };
+
+    Literal { hash: hash(&bytes), bytes, reversed, power }
+  }
+
+
+  /// Whether the window, with the given hash, is an occurrence.
+  fn is_occurrence(&self, window: &[u8], window_hash: u64) -> bool {
+    (window_hash == self.hash && window == &self.bytes[..])
+      || self.reversed
+             .as_ref()
+             .is_some_and(|(reversed, hash)| window_hash == *hash && window == &reversed[..])
+  }
+
+
+  /// Iterate the offsets of the occurrences in the buffer. As with the regex engine,
+  /// occurrences don't overlap.
+  pub fn find_iter<'a>(&'a self, buffer: &'a [u8]) -> Matches<'a> {
+    Matches { literal: self, buffer, start: 0, hash: None }
+  }
+}
+
+
+/// The iterator of the occurrences of a literal.
+pub struct Matches<'a> {
+  literal: &'a Literal,
+  buffer: &'a [u8],
+  start: usize,     // The start of the current window.
+  hash: Option<u64> // The hash of the current window, unless it must be computed anew.
+}
+
+
+impl<'a> Iterator for Matches<'a> {
+  type Item = usize;
+
+  fn next(&mut self) -> Option<usize> {
+    let len = self.literal.bytes.len();
+
+    loop {
+      let end = self.start.checked_add(len)?;
+      let window = self.buffer.get(self.start .. end)?;
+
+      let hash = self.hash.unwrap_or_else(|| hash(window));
+
+      if self.literal.is_occurrence(window, hash) {
+        // Skip past the occurrence, where the hash must be computed anew.
+        let start = self.start;
+        self.start = end;
+        self.hash = None;
+
+        return Some(start);
+      }
+
+      // Roll the window one byte forward.
+      let next = *self.buffer.get(end)?;
+
+      self.hash = Some(
+        hash.wrapping_sub((window[0] as u64).wrapping_mul(self.literal.power))
+            .wrapping_mul(BASE)
+            .wrapping_add(next as u64)
+      );
+      self.start += 1;
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use regex::bytes::Regex;
+
+
+  /// The offsets found by the regex engine for the literal, and its reverse if requested.
+  fn regex_offsets(bytes: &[u8], also_reversed: bool, buffer: &[u8]) -> Vec<usize> {
+    let escape = |bytes: &[u8]| -> String {
+      bytes.iter().map(|b| format!(r"\x{:02x}", b)).collect()
+    };
+
+    let mut pattern = format!("(?-u){}", escape(bytes));
+    if also_reversed {
+      let reversed: Vec<u8> = bytes.iter().rev().copied().collect();
+      pattern = format!("{}|{}", pattern, escape(&reversed));
+    }
+
+    Regex::new(&pattern).unwrap().find_iter(buffer).map(|m| m.start()).collect()
+  }
+
+
+  /// Check that the literal finds the same offsets as the regex engine.
+  fn check(bytes: &[u8], also_reversed: bool, buffer: &[u8]) -> Vec<usize> {
+    let literal = Literal::new(bytes.to_vec(), also_reversed);
+    let offsets: Vec<usize> = literal.find_iter(buffer).collect();
+
+    assert_eq!(offsets, regex_offsets(bytes, also_reversed, buffer), "literal {:?}", bytes);
+
+    offsets
+  }
+
+
+  #[test]
+  fn overlapping_occurrences_skipped() {
+    assert_eq!(check(b"aa", false, b"aaaaa"), [0, 2]);
+    assert_eq!(check(b"aba", false, b"ababababa"), [0, 4]);
+  }
+
+
+  #[test]
+  fn repeated_prefixes() {
+    assert_eq!(check(b"aab", false, b"aaaab aab"), [2, 6]);
+    assert_eq!(check(b"abab", false, b"abaababab"), [3]);
+  }
+
+
+  #[test]
+  fn buffer_ends() {
+    assert_eq!(check(b"\x00\xff", false, b"\x00\xff-\x00\xff"), [0, 3]);
+    assert_eq!(check(b"abc", false, b"abc"), [0]);
+    assert_eq!(check(b"abc", false, b"ab"), []);
+    assert_eq!(check(b"x", false, b""), []);
+  }
+
+
+  #[test]
+  fn hash_collisions_compared() {
+    let literal = Literal::new(b"ab".to_vec(), false);
+
+    // A window with the same hash but other bytes is not an occurrence.
+    assert!(!literal.is_occurrence(b"ba", literal.hash));
+    assert!(literal.is_occurrence(b"ab", literal.hash));
+
+    // The bytes wrap around modulo 2^64 for long literals.
+    let bytes: Vec<u8> = (0 ..= 255).cycle().take(300).collect();
+    let buffer = [&bytes[1 ..], &bytes, &bytes[.. 10]].concat();
+    assert_eq!(check(&bytes, false, &buffer), [299]);
+  }
+
+
+  #[test]
+  fn also_reversed() {
+    assert_eq!(check(b"abc", true, b"abc cba abcba"), [0, 4, 8]);
+    assert_eq!(check(b"aba", true, b"ababa"), [0]); // Palindromes match once.
+    assert_eq!(check(b"ab", true, b"aba"), [0]);
+    assert_eq!(check(b"ab", true, b"bab"), [0]);
+  }
+}
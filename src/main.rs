@@ -1,5 +1,19 @@
+mod alternation;
 mod args;
+mod digest;
+mod encoding;
+mod format;
 mod grep;
+mod hex;
+mod literal;
+mod manifest;
+mod mask;
+mod mtime;
+mod pe;
+mod prefilter;
+mod progress;
+mod record;
+mod resume;
 
 use std::env;
 use std::io::{self, Write};
@@ -30,7 +44,7 @@ fn main() -> ! {
 
 
     match command {
-      Command::Grep(args) => grep::run(args, &mut stdout),
+      Command::Grep(args) => grep::run(*args, &mut stdout),
       Command::Help(msg) | Command::Version(msg) => {
         writeln!(stdout, "{}", msg)?;
         Ok(true)
@@ -42,6 +56,7 @@ fn main() -> ! {
     match run() {
       Ok(true)  => 0, // There was at least one match.
       Ok(false) => 1, // There was no match.
+      Err(ref e) if e.get_ref().is_some_and(|e| e.is::<grep::NoMatch>()) => 6,
       Err(e) => match e.kind() {
         io::ErrorKind::InvalidInput     => 3,
         io::ErrorKind::NotFound         => 4,
@@ -0,0 +1,90 @@
+use std::path::Path;
+
+
+/// An entry of the manifest, as specified by `--manifest`: the pattern applies to the
+/// files that match the glob.
+#[derive(Debug)]
+pub struct Entry {
+  pub glob: String,
+  pub pattern: String
+}
+
+
+/// Parse a manifest, with one `<glob> => <pattern>` entry per line. Blank lines and lines
+/// starting with `#` are ignored. Surrounding whitespace is trimmed from both sides.
+/// Returns the error message if a line is malformed.
+pub fn parse(manifest: &str) -> Result<Vec<Entry>, String> {
+  let mut entries = Vec::new();
+
+  for (ix, line) in manifest.lines().enumerate() {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let separator = line.find("=>")
+                        .ok_or_else(|| format!("missing '=>' in line {}", ix + 1))?;
+
+    let glob = line[.. separator].trim();
+    let pattern = line[separator + 2 ..].trim();
+
+    if glob.is_empty() || pattern.is_empty() {
+      return Err(format!("empty glob or pattern in line {}", ix + 1));
+    }
+
+    entries.push(Entry { glob: glob.to_owned(), pattern: pattern.to_owned() });
+  }
+
+  Ok(entries)
+}
+
+
+/// Check whether the path matches the glob, where `*` matches any sequence of characters
+/// and `?` matches a single character. Globs containing a `/` are matched against the
+/// whole path, and otherwise against the file name.
+pub fn matches<P: AsRef<Path>>(glob: &str, path: P) -> bool {
+  let path = path.as_ref();
+
+  let subject =
+    if glob.contains('/') {
+      path.to_string_lossy()
+    } else {
+      match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false
+      }
+    };
+
+  let glob: Vec<char> = glob.chars().collect();
+  let subject: Vec<char> = subject.chars().collect();
+
+  let (mut g, mut s) = (0, 0);
+  let mut backtrack = None; // The positions after the last `*`, to retry from.
+
+  while s < subject.len() {
+    match glob.get(g) {
+      Some('*') => {
+        g += 1;
+        backtrack = Some((g, s));
+      }
+
+      Some(&c) if c == '?' || c == subject[s] => {
+        g += 1;
+        s += 1;
+      }
+
+      // Let the last `*` consume one more character.
+      _ => match backtrack {
+        Some((bg, bs)) => {
+          g = bg;
+          s = bs + 1;
+          backtrack = Some((bg, bs + 1));
+        }
+        None => return false
+      }
+    }
+  }
+
+  glob[g ..].iter().all(|&c| c == '*')
+}
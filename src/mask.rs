@@ -0,0 +1,26 @@
+/// A byte signature where only the bits set in the mask must match, as specified by
+/// `--mask`.
+#[derive(Debug)]
+pub struct Mask {
+  pub bytes: Vec<u8>,
+  pub mask: Vec<u8> // Same length as bytes.
+}
+
+
+impl Mask {
+  /// Iterate the offsets of all occurrences in the buffer, including overlapping ones.
+  pub fn find_iter<'a>(&'a self, buffer: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    buffer.windows(self.bytes.len())
+          .enumerate()
+          .filter(move |(_, window)| self.is_match(window))
+          .map(|(ix, _)| ix)
+  }
+
+
+  /// Whether the window, which must have the same length as the signature, matches.
+  fn is_match(&self, window: &[u8]) -> bool {
+    window.iter()
+          .zip(self.bytes.iter().zip(self.mask.iter()))
+          .all(|(w, (b, m))| (w ^ b) & m == 0)
+  }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+
+/// The modification times of the searched files, as saved with `--only-files-changed`.
+pub type Mtimes = HashMap<PathBuf, Duration>;
+
+
+/// The modification time of the file, as the duration since the unix epoch.
+pub fn of<P: AsRef<Path>>(path: P) -> io::Result<Duration> {
+  let modified = fs::metadata(path)?.modified()?;
+
+  Ok(modified.duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+
+/// Load the modification times from the given path. Returns an empty map if the file
+/// doesn't exist.
+/// The format is one `<seconds>.<nanoseconds>\t<path>` entry per line.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Mtimes> {
+  let contents = match fs::read_to_string(path) {
+    Ok(contents) => contents,
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Mtimes::new()),
+    Err(e) => return Err(e)
+  };
+
+  let invalid = || io::Error::from(io::ErrorKind::InvalidData);
+
+  let mut mtimes = Mtimes::new();
+
+  for line in contents.lines() {
+    let tab = line.find('\t').ok_or_else(invalid)?;
+
+    let (seconds, nanos) = match line[.. tab].find('.') {
+      Some(dot) => (&line[.. dot], &line[dot + 1 .. tab]),
+      None => return Err(invalid())
+    };
+
+    let mtime = Duration::new(
+      seconds.parse().map_err(|_| invalid())?,
+      nanos.parse().map_err(|_| invalid())?
+    );
+
+    mtimes.insert(PathBuf::from(&line[tab + 1 ..]), mtime);
+  }
+
+  Ok(mtimes)
+}
+
+
+/// Save the modification times to the given path, sorted by path.
+pub fn save<P: AsRef<Path>>(path: P, mtimes: &Mtimes) -> io::Result<()> {
+  let mut entries: Vec<_> = mtimes.iter().collect();
+  entries.sort();
+
+  let contents: String = entries.into_iter()
+                                .map(
+                                  |(path, mtime)| format!(
+                                    "{}.{:09}\t{}\n",
+                                    mtime.as_secs(),
+                                    mtime.subsec_nanos(),
+                                    path.display()
+                                  )
+                                )
+                                .collect();
+
+  fs::write(path, contents)
+}
+
@@ -0,0 +1,68 @@
+use std::ops::Range;
+
+
+/// A section of a PE file, as selected by `--pe-section`.
+#[derive(Debug)]
+pub struct Section {
+  pub range: Range<usize>, // The range of the section's raw data in the file.
+  pub virtual_address: usize // The RVA of the section, where its data is loaded.
+}
+
+
+/// Read a little endian integer of `N` bytes at the given offset.
+fn read_le<const N: usize>(buffer: &[u8], offset: usize) -> Result<u64, String> {
+  let bytes = offset.checked_add(N)
+                    .and_then(|end| buffer.get(offset .. end))
+                    .ok_or_else(|| "truncated PE header".to_owned())?;
+
+  Ok(bytes.iter().rev().fold(0, |value, &b| value << 8 | b as u64))
+}
+
+
+/// Find the section with the given name in the PE file, e.g. `.rdata`.
+/// Returns the error message if the buffer is not a PE file, or if the section is missing.
+pub fn find_section(buffer: &[u8], name: &str) -> Result<Section, String> {
+  if !buffer.starts_with(b"MZ") {
+    return Err("missing PE signature".to_owned());
+  }
+
+  // The DOS header points to the PE signature, which is followed by the COFF header.
+  let pe = read_le::<4>(buffer, 0x3c)? as usize;
+
+  if buffer.get(pe .. pe + 4) != Some(b"PE\0\0") {
+    return Err("missing PE signature".to_owned());
+  }
+
+  let coff = pe + 4;
+  let sections = read_le::<2>(buffer, coff + 2)? as usize;
+  let optional_header_size = read_le::<2>(buffer, coff + 16)? as usize;
+
+  // The section table follows the optional header, with 40 bytes per section.
+  let table = coff + 20 + optional_header_size;
+
+  for ix in 0 .. sections {
+    let entry = table + ix * 40;
+
+    let raw_name = buffer.get(entry .. entry + 8)
+                         .ok_or_else(|| "truncated section table".to_owned())?;
+
+    // Names are padded with nulls, and are not terminated when they take all 8 bytes.
+    let length = raw_name.iter().position(|&b| b == 0).unwrap_or(8);
+
+    if &raw_name[.. length] != name.as_bytes() {
+      continue;
+    }
+
+    let virtual_address = read_le::<4>(buffer, entry + 12)? as usize;
+    let size = read_le::<4>(buffer, entry + 16)? as usize;
+    let start = read_le::<4>(buffer, entry + 20)? as usize;
+
+    let end = start.checked_add(size)
+                   .filter(|&end| end <= buffer.len())
+                   .ok_or_else(|| format!("section '{}' extends beyond the file", name))?;
+
+    return Ok(Section { range: start .. end, virtual_address });
+  }
+
+  Err(format!("section '{}' not found", name))
+}
@@ -0,0 +1,163 @@
+use memchr::memchr;
+use regex::bytes::Match;
+
+use crate::alternation::{self, Alternation};
+
+
+/// The matches of the patterns, either all of them, or only those starting with the byte
+/// given by `--prefilter`. Each match comes with the index of the pattern that produced it.
+pub enum Candidates<'r, 't> {
+  All(alternation::Matches<'r, 't>),
+  Prefiltered(Prefiltered<'r, 't>)
+}
+
+
+impl<'r, 't> Iterator for Candidates<'r, 't> {
+  type Item = (usize, Match<'t>);
+
+  fn next(&mut self) -> Option<(usize, Match<'t>)> {
+    match self {
+      Candidates::All(matches) => matches.next(),
+      Candidates::Prefiltered(matches) => matches.next()
+    }
+  }
+}
+
+
+/// Iterate the matches of `patterns` in `buffer`, restricted to those starting with `byte`
+/// if given.
+pub fn find_iter<'r, 't>(
+  patterns: &'r Alternation,
+  byte: Option<u8>,
+  buffer: &'t [u8]
+) -> Candidates<'r, 't> {
+  match byte {
+    None => Candidates::All(patterns.find_iter(buffer)),
+    Some(byte) => Candidates::Prefiltered(Prefiltered { patterns, byte, buffer, position: 0 })
+  }
+}
+
+
+/// The matches that start with the given byte. The candidate positions are found with
+/// `memchr`, and the patterns are only run from there.
+pub struct Prefiltered<'r, 't> {
+  patterns: &'r Alternation,
+  byte: u8,
+  buffer: &'t [u8],
+  position: usize // Where the search for the next candidate starts.
+}
+
+
+impl<'r, 't> Iterator for Prefiltered<'r, 't> {
+  type Item = (usize, Match<'t>);
+
+  fn next(&mut self) -> Option<(usize, Match<'t>)> {
+    loop {
+      let rest = self.buffer.get(self.position ..)?;
+      let candidate = self.position + memchr(self.byte, rest)?;
+
+      // The leftmost match from the candidate. When it starts with another byte, no match
+      // starts before it, so the search resumes right after its start.
+      let (ix, m) = self.patterns.find_at(self.buffer, candidate)?;
+
+      if self.buffer.get(m.start()) != Some(&self.byte) {
+        self.position = m.start() + 1;
+        continue;
+      }
+
+      // Empty matches must still advance the position.
+      self.position = if m.end() == m.start() { m.end() + 1 } else { m.end() };
+
+      return Some((ix, m));
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use regex::bytes::Regex;
+
+
+  fn alternation(patterns: &[&str]) -> Alternation {
+    Alternation::new(patterns.iter().map(|p| Regex::new(p).unwrap()).collect())
+  }
+
+
+  /// The matches starting with the byte, found by trying every position in turn.
+  fn naive(patterns: &Alternation, byte: u8, buffer: &[u8]) -> Vec<(usize, usize, usize)> {
+    let mut matches = Vec::new();
+    let mut position = 0;
+
+    while position < buffer.len() {
+      match patterns.find_at(buffer, position) {
+        Some((ix, m)) if m.start() == position && buffer[position] == byte => {
+          matches.push((ix, m.start(), m.end()));
+          position = if m.end() == m.start() { m.end() + 1 } else { m.end() };
+        }
+        _ => position += 1
+      }
+    }
+
+    matches
+  }
+
+
+  /// The candidates, with the pattern index, start and end of each.
+  fn spans(patterns: &Alternation, byte: Option<u8>, buffer: &[u8]) -> Vec<(usize, usize, usize)> {
+    find_iter(patterns, byte, buffer).map(|(ix, m)| (ix, m.start(), m.end())).collect()
+  }
+
+
+  /// Check that the prefiltered matches are those of the naive path.
+  fn check(patterns: &[&str], byte: u8, buffer: &[u8]) -> Vec<(usize, usize, usize)> {
+    let patterns = alternation(patterns);
+    let matches = spans(&patterns, Some(byte), buffer);
+
+    assert_eq!(matches, naive(&patterns, byte, buffer));
+
+    matches
+  }
+
+
+  #[test]
+  fn absent_byte() {
+    assert_eq!(check(&["a+"], b'z', b"aaa"), []);
+    assert_eq!(check(&["a+"], b'a', b""), []);
+  }
+
+
+  #[test]
+  fn match_starting_before_byte() {
+    // The leftmost match overlaps the candidate, which is searched again after its start.
+    assert_eq!(check(&["[ab]a"], b'a', b"baa"), [(0, 1, 3)]);
+    assert_eq!(check(&["xa", "a"], b'a', b"xa"), [(1, 1, 2)]);
+    assert_eq!(check(&["xa"], b'a', b"xaxa"), []);
+  }
+
+
+  #[test]
+  fn adjacent_candidates() {
+    assert_eq!(check(&["a"], b'a', b"aaa"), [(0, 0, 1), (0, 1, 2), (0, 2, 3)]);
+    assert_eq!(check(&["aa"], b'a', b"aaaaa"), [(0, 0, 2), (0, 2, 4)]);
+    assert_eq!(check(&["ab", "a"], b'a', b"aab"), [(1, 0, 1), (0, 1, 3)]);
+  }
+
+
+  #[test]
+  fn buffer_end() {
+    assert_eq!(check(&["a$"], b'a', b"aba"), [(0, 2, 3)]);
+    assert_eq!(check(&["ab"], b'a', b"xa"), []);
+    assert_eq!(check(&["a?"], b'a', b"ba"), [(0, 1, 2)]);
+  }
+
+
+  #[test]
+  fn unfiltered_when_every_match_starts_with_byte() {
+    let patterns = alternation(&["a[0-9]+", "ab"]);
+    let buffer = b"a1 ab a22b xa3";
+    assert_eq!(spans(&patterns, Some(b'a'), buffer), spans(&patterns, None, buffer));
+  }
+}
@@ -0,0 +1,95 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use regex::bytes::Match;
+
+
+/// The position of the chunk being searched in the input, when reading in chunks.
+#[derive(Clone, Copy, Debug)]
+pub struct Window {
+  pub base: usize, // The offset of the chunk in the input.
+  pub total: usize // The size of the input, or 0 if unknown.
+}
+
+
+/// An iterator adapter over matches, with the index of the pattern of each, that reports to
+/// stderr how far through the input the matching has advanced. The position is
+/// approximated by the end of the latest match.
+pub struct Progress<I> {
+  matches: I,
+  label: Option<String>, // None when disabled.
+  base: usize, // The offset of the buffer in the input.
+  total: Option<usize>, // The size of the input, if known.
+  end: usize, // The offset of the end of the buffer in the input.
+  reported: Option<usize> // The last reported percentage, or MiB when the size is unknown.
+}
+
+
+impl<I> Progress<I> {
+  fn report(&mut self, position: usize) {
+    if let Some(ref label) = self.label {
+      let position = self.base + position;
+
+      // The size of stdin and devices is unknown, so only the amount searched is reported.
+      let (reported, unit) = match self.total {
+        Some(total) => ((position * 100).checked_div(total).unwrap_or(100), "%"),
+        None => (position >> 20, " MiB")
+      };
+
+      if self.reported != Some(reported) {
+        self.reported = Some(reported);
+        // Progress is best effort, errors writing to stderr are ignored.
+        let _ = write!(io::stderr(), "\r{}: {}{}", label, reported, unit);
+      }
+    }
+  }
+}
+
+
+impl<'t, I: Iterator<Item = (usize, Match<'t>)>> Iterator for Progress<I> {
+  type Item = (usize, Match<'t>);
+
+  fn next(&mut self) -> Option<(usize, Match<'t>)> {
+    let m = self.matches.next();
+
+    let position = m.as_ref().map_or(self.end - self.base, |(_, m)| m.end());
+    self.report(position);
+
+    m
+  }
+}
+
+
+impl<I> Drop for Progress<I> {
+  fn drop(&mut self) {
+    if self.label.is_some() && self.reported.is_some() {
+      let _ = write!(io::stderr(), "\r\x1b[K"); // Clear the progress line.
+    }
+  }
+}
+
+
+/// Track the progress of the given matches over a buffer of `len` bytes, which is the
+/// given window of the input if reading in chunks, or otherwise the whole input.
+/// When `enabled` is false, the matches are passed through without any reporting.
+pub fn track<I, P: Display>(
+  matches: I,
+  enabled: bool,
+  path: P,
+  window: Option<Window>,
+  len: usize
+) -> Progress<I> {
+  let (base, total) = match window {
+    Some(window) => (window.base, Some(window.total).filter(|&total| total > 0)),
+    None => (0, Some(len))
+  };
+
+  Progress {
+    matches,
+    label: if enabled { Some(path.to_string()) } else { None },
+    base,
+    total,
+    end: base + len,
+    reported: None
+  }
+}
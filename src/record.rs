@@ -0,0 +1,78 @@
+use std::ops::Range;
+
+
+/// The format of length-prefixed records, as specified by `--record-format`.
+#[derive(Debug, Clone, Copy)]
+pub enum RecordFormat {
+  Netstring, // <decimal length>:<bytes>,
+  Len32Le    // <u32 little endian length><bytes>
+}
+
+
+/// The error type for record splitting. Contains the index of the malformed record and
+/// the error message.
+#[derive(Debug)]
+pub struct Error {
+  pub record: usize,
+  pub message: String
+}
+
+
+/// Split the buffer into the ranges of the records' contents.
+pub fn split(format: RecordFormat, buffer: &[u8]) -> Result<Vec<Range<usize>>, Error> {
+  let mut records = Vec::new();
+  let mut position = 0;
+
+  while position < buffer.len() {
+    let error = |message: &str| Error { record: records.len(), message: message.to_owned() };
+
+    let (start, length) = match format {
+      RecordFormat::Netstring => {
+        let colon = buffer[position ..].iter()
+                                       .position(|&b| b == b':')
+                                       .ok_or_else(|| error("missing ':' after length"))?;
+
+        let digits = &buffer[position .. position + colon];
+
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+          return Err(error("invalid length"));
+        }
+
+        let length = std::str::from_utf8(digits)
+                              .expect("ASCII digits are valid UTF-8")
+                              .parse::<usize>()
+                              .map_err(|_| error("invalid length"))?;
+
+        (position + colon + 1, length)
+      }
+
+      RecordFormat::Len32Le => {
+        let header = buffer.get(position .. position + 4)
+                           .ok_or_else(|| error("truncated length"))?;
+
+        let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+
+        (position + 4, length as usize)
+      }
+    };
+
+    let end = start.checked_add(length)
+                   .filter(|&end| end <= buffer.len())
+                   .ok_or_else(|| error("truncated contents"))?;
+
+    position = match format {
+      RecordFormat::Netstring =>
+        if buffer.get(end) == Some(&b',') {
+          end + 1
+        } else {
+          return Err(error("missing ',' after contents"));
+        },
+
+      RecordFormat::Len32Le => end
+    };
+
+    records.push(start .. end);
+  }
+
+  Ok(records)
+}
@@ -0,0 +1,42 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+
+/// The position of a scan, as saved with `--resume-from`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Checkpoint {
+  pub files: usize, // The number of files that were completely processed.
+  pub offset: usize // The offset reached in the next file, only advanced in chunks.
+}
+
+
+/// Load the checkpoint from the given path. Returns `None` if the file doesn't exist.
+/// The format is two lines: the number of processed files, and the offset in the next file.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Option<Checkpoint>> {
+  let contents = match fs::read_to_string(path) {
+    Ok(contents) => contents,
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(e)
+  };
+
+  let mut lines = contents.lines().map(str::parse::<usize>);
+
+  match (lines.next(), lines.next()) {
+    (Some(Ok(files)), Some(Ok(offset))) => Ok(Some(Checkpoint { files, offset })),
+    _ => Err(io::Error::from(io::ErrorKind::InvalidData))
+  }
+}
+
+
+/// Save the checkpoint to the given path. The file is replaced atomically, so that an
+/// interruption never leaves a truncated checkpoint behind.
+pub fn save<P: AsRef<Path>>(path: P, checkpoint: &Checkpoint) -> io::Result<()> {
+  let path = path.as_ref();
+
+  let mut temp = path.as_os_str().to_owned();
+  temp.push(".tmp");
+
+  fs::write(&temp, format!("{}\n{}\n", checkpoint.files, checkpoint.offset))?;
+  fs::rename(&temp, path)
+}